@@ -0,0 +1,133 @@
+use draw_script::paintboard::ColorArray;
+use draw_script::Config;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const WIDTH: usize = 1000;
+const HEIGHT: usize = 600;
+const READERS: usize = 4;
+
+/// 原来的实现：整个画板共用一个 Mutex，作为对照
+struct MutexArray {
+    array: Mutex<Vec<Vec<Option<usize>>>>,
+}
+
+impl MutexArray {
+    fn new() -> MutexArray {
+        MutexArray {
+            array: Mutex::new(vec![vec![Some(1); HEIGHT]; WIDTH]),
+        }
+    }
+    fn color(&self, x: usize, y: usize) -> Option<usize> {
+        self.array.lock().unwrap()[x][y]
+    }
+    fn set_color(&self, x: usize, y: usize, color: Option<usize>) {
+        self.array.lock().unwrap()[x][y] = color;
+    }
+}
+
+fn board() -> Vec<Vec<usize>> {
+    (0..WIDTH)
+        .map(|x| (0..HEIGHT).map(|y| (x * 7 + y * 3) % 32).collect())
+        .collect()
+}
+
+fn color_array() -> ColorArray {
+    ColorArray::new(Arc::new(Config {
+        board_width: WIDTH,
+        board_height: HEIGHT,
+        ..Config::default()
+    }))
+}
+
+/// 在 READERS 个线程不断读取画板的同时执行 f
+fn with_readers<R, F>(read: R, f: F)
+where
+    R: Fn(usize, usize) -> Option<usize> + Sync,
+    F: FnOnce(),
+{
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for i in 0..READERS {
+            let (read, stop) = (&read, &stop);
+            scope.spawn(move || {
+                let mut n = i;
+                while !stop.load(Ordering::Relaxed) {
+                    n = (n + 7919) % (WIDTH * HEIGHT);
+                    criterion::black_box(read(n / HEIGHT, n % HEIGHT));
+                }
+            });
+        }
+        f();
+        stop.store(true, Ordering::Relaxed);
+    });
+}
+
+fn refresh(c: &mut Criterion) {
+    let board = board();
+    let mut group = c.benchmark_group("refresh");
+    group.sample_size(20);
+
+    let mutex = MutexArray::new();
+    group.bench_function("mutex_per_pixel", |b| {
+        b.iter(|| {
+            for (x, line) in board.iter().enumerate() {
+                for (y, color) in line.iter().enumerate() {
+                    mutex.set_color(x, y, Some(*color));
+                }
+            }
+        })
+    });
+    let array = color_array();
+    group.bench_function("atomic_set_color", |b| {
+        b.iter(|| {
+            for (x, line) in board.iter().enumerate() {
+                for (y, color) in line.iter().enumerate() {
+                    array.set_color(x, y, Some(*color)).unwrap();
+                }
+            }
+        })
+    });
+    group.bench_function("atomic_replace_all", |b| {
+        b.iter(|| array.replace_all(&board).unwrap())
+    });
+    group.finish();
+}
+
+fn refresh_with_readers(c: &mut Criterion) {
+    let board = board();
+    let mut group = c.benchmark_group("refresh_with_readers");
+    group.sample_size(10);
+
+    let mutex = MutexArray::new();
+    group.bench_function("mutex_per_pixel", |b| {
+        b.iter(|| {
+            with_readers(
+                |x, y| mutex.color(x, y),
+                || {
+                    for (x, line) in board.iter().enumerate() {
+                        for (y, color) in line.iter().enumerate() {
+                            mutex.set_color(x, y, Some(*color));
+                        }
+                    }
+                },
+            )
+        })
+    });
+    let array = color_array();
+    group.bench_function("atomic_replace_all", |b| {
+        b.iter(|| {
+            with_readers(
+                |x, y| array.color(x, y),
+                || array.replace_all(&board).unwrap(),
+            )
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, refresh, refresh_with_readers);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+use super::{Config, ScriptError};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+/// 配置文件中的 `[http]`
+pub struct HttpConfig {
+    /// 建立连接的超时时间（秒）
+    pub connect_timeout: u64,
+    /// 单个请求的超时时间（秒）
+    pub timeout: u64,
+    /// HTTP/HTTPS/SOCKS5 代理，例如 `socks5://127.0.0.1:1080`
+    pub proxy: Option<String>,
+    pub user_agent: String,
+    /// 附加在每个请求上的 Header
+    pub headers: BTreeMap<String, String>,
+    /// 是否校验 TLS 证书，仅应在连接本地测试服务器时关闭
+    pub verify_tls: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> HttpConfig {
+        HttpConfig {
+            connect_timeout: 10,
+            timeout: 30,
+            proxy: None,
+            user_agent: concat!("draw-script/", env!("CARGO_PKG_VERSION")).to_string(),
+            headers: BTreeMap::new(),
+            verify_tls: true,
+        }
+    }
+}
+
+/// 根据配置构建所有绘板请求共用的 HTTP 客户端
+pub fn build_client(config: &Config) -> Result<Client, ScriptError> {
+    let http = &config.http;
+    let mut headers = HeaderMap::new();
+    headers.insert(header::REFERER, config.board_addr.parse()?);
+    for (name, value) in &http.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| ScriptError::InvalidHeaderName(name.clone()))?;
+        headers.insert(name, HeaderValue::from_str(value)?);
+    }
+
+    let mut builder = Client::builder()
+        .default_headers(headers)
+        .user_agent(http.user_agent.as_str())
+        .connect_timeout(Duration::from_secs(http.connect_timeout))
+        .timeout(Duration::from_secs(http.timeout))
+        .danger_accept_invalid_certs(!http.verify_tls);
+    if let Some(proxy) = &http.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(http: HttpConfig) -> Config {
+        Config {
+            http,
+            ..Config::default()
+        }
+    }
+
+    fn with_header(name: &str, value: &str) -> Config {
+        let mut http = HttpConfig::default();
+        http.headers.insert(name.to_string(), value.to_string());
+        config(http)
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(matches!(
+            build_client(&with_header("bad header", "1")),
+            Err(ScriptError::InvalidHeaderName(name)) if name == "bad header"
+        ));
+        assert!(matches!(
+            build_client(&with_header("X-Test", "line\nbreak")),
+            Err(ScriptError::FailedParseHeader(_))
+        ));
+        assert!(build_client(&with_header("X-Test", "1")).is_ok());
+    }
+
+    #[test]
+    fn proxy_and_tls() {
+        for proxy in ["http://127.0.0.1:8080", "socks5://127.0.0.1:1080"] {
+            let http = HttpConfig {
+                proxy: Some(proxy.to_string()),
+                verify_tls: false,
+                ..HttpConfig::default()
+            };
+            assert!(build_client(&config(http)).is_ok());
+        }
+    }
+}
@@ -1,10 +1,18 @@
+use super::client::HttpConfig;
+use super::cookie::CookieBackoffConfig;
+use super::init;
+use super::palette::Palette;
+use super::ratelimit::RateLimitConfig;
 use super::ScriptError;
 use super::UrlError;
 
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub board_addr: String,
     pub websocket_addr: String,
@@ -14,34 +22,466 @@ pub struct Config {
     pub thread_num: usize,
     pub board_width: usize,
     pub board_height: usize,
+    pub palette: Palette,
+    pub exit_on_complete: bool,
+    pub http: HttpConfig,
+    pub rate_limit: RateLimitConfig,
+    /// 启动前检测所有 Cookie，并丢弃失效的 Cookie
+    pub check_cookies: bool,
+    /// 失效 Cookie 文件的移动目标
+    pub quarantine_dir: Option<String>,
+    /// 重新读取 cookie_dir 的间隔（秒），0 表示不重新读取
+    pub cookie_reload_interval: u64,
+    /// 检查 node_file 是否被修改的间隔（秒），0 表示不检查
+    pub node_reload_interval: u64,
+    /// 保存 Cookie 状态的文件，重启后据此恢复冷却时间和失效标记
+    pub cookie_state_file: Option<String>,
+    pub cookie_backoff: CookieBackoffConfig,
+    /// 画板快照缓存，启动时在获取到画板前使用，每次获取画板后更新
+    pub board_cache: Option<String>,
+    /// 输出绘制进度的间隔（秒），0 表示不输出
+    pub progress_interval: u64,
 }
 
-impl Config {
-    fn from_toml(raw_config: &str) -> Result<Config, ScriptError> {
-        let config: Config = toml::from_str(raw_config)?;
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            board_addr: "https://www.luogu.com.cn/paintboard".to_string(),
+            websocket_addr: "wss://ws.luogu.com.cn/ws".to_string(),
+            cookie_dir: "cookie_dir".to_string(),
+            node_file: "node.json".to_string(),
+            wait_time: 30,
+            thread_num: 8,
+            board_width: 1000,
+            board_height: 600,
+            palette: Palette::default(),
+            exit_on_complete: false,
+            http: HttpConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            check_cookies: false,
+            quarantine_dir: None,
+            cookie_reload_interval: 10,
+            node_reload_interval: 10,
+            cookie_state_file: None,
+            cookie_backoff: CookieBackoffConfig::default(),
+            board_cache: None,
+            progress_interval: 60,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// 配置中的一处问题
+pub enum ConfigError {
+    /// 配置项名称与地址不合法的原因
+    InvalidAddress(&'static str, ScriptError),
+    InvalidPalette(ScriptError),
+    /// 线程数不足以在后台线程之外运行至少一个绘制线程
+    TooFewThreads {
+        thread_num: usize,
+        workers: usize,
+    },
+    EmptyBoard {
+        width: usize,
+        height: usize,
+    },
+    /// 配置项名称、路径与无法读取的原因
+    Unreadable(&'static str, String, std::io::Error),
+    NotADirectory(String),
+    InvalidNodeFile(ScriptError),
+    /// 超出画板范围的点数与其中第一个点
+    NodeOutOfBounds {
+        count: usize,
+        x: usize,
+        y: usize,
+    },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::InvalidAddress(key, err) => write!(formatter, "{} 不合法: {}", key, err),
+            ConfigError::InvalidPalette(err) => write!(formatter, "palette 不合法: {}", err),
+            ConfigError::TooFewThreads {
+                thread_num,
+                workers,
+            } => write!(
+                formatter,
+                "thread_num 为 {}，但后台线程需要占用 {} 个，请至少设置为 {}",
+                thread_num,
+                workers,
+                workers + 1
+            ),
+            ConfigError::EmptyBoard { width, height } => write!(
+                formatter,
+                "画板大小 {}x{} 不合法，board_width 与 board_height 必须大于 0",
+                width, height
+            ),
+            ConfigError::Unreadable(key, path, err) => {
+                write!(formatter, "{} 无法读取 {}: {}", key, path, err)
+            }
+            ConfigError::NotADirectory(path) => {
+                write!(formatter, "cookie_dir {} 不是文件夹", path)
+            }
+            ConfigError::InvalidNodeFile(err) => write!(formatter, "node_file 不合法: {}", err),
+            ConfigError::NodeOutOfBounds { count, x, y } => write!(
+                formatter,
+                "node_file 中有 {} 个点超出画板范围，例如 ({}, {})",
+                count, x, y
+            ),
+        }
+    }
+}
+
+/// 覆盖配置文件中的一项，键为配置项名称，`[rate_limit]` 等表中的项用 `.` 连接，
+/// 例如 `rate_limit.requests_per_second`
+pub type Override = (String, toml::Value);
+
+/// 环境变量的前缀，`[rate_limit]` 等表中的项用 `__` 连接，
+/// 例如 `LUOGU_PAINT_RATE_LIMIT__REQUESTS_PER_SECOND`
+pub const ENV_PREFIX: &str = "LUOGU_PAINT_";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 配置项的来源
+pub enum ConfigSource {
+    Default,
+    File,
+    /// 来自该环境变量
+    Env(String),
+    /// 来自命令行参数
+    Cli,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigSource::Default => write!(formatter, "default"),
+            ConfigSource::File => write!(formatter, "file"),
+            ConfigSource::Env(name) => write!(formatter, "env {}", name),
+            ConfigSource::Cli => write!(formatter, "command line"),
+        }
+    }
+}
+
+/// 将 value 写入 table 中 key 对应的位置，必要时创建中间的表
+fn set_value(table: &mut toml::value::Table, key: &str, value: toml::Value) {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let entry = table
+                .entry(head.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+            if !entry.is_table() {
+                *entry = toml::Value::Table(toml::value::Table::new());
+            }
+            if let toml::Value::Table(inner) = entry {
+                set_value(inner, rest, value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// 将嵌套的表展开为 `a.b = value` 形式，数组视为单个值
+fn flatten(prefix: &str, table: &toml::value::Table, output: &mut BTreeMap<String, toml::Value>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            toml::Value::Table(inner) => flatten(&key, inner, output),
+            value => {
+                output.insert(key, value.clone());
+            }
+        }
+    }
+}
+
+/// 将 value 写成单行的 TOML
+fn inline(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Array(values) => format!(
+            "[{}]",
+            values.iter().map(inline).collect::<Vec<_>>().join(", ")
+        ),
+        toml::Value::Table(table) => format!(
+            "{{ {} }}",
+            table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, inline(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        value => value.to_string(),
+    }
+}
+
+/// 将环境变量的值解析为 TOML 值，无法解析，或解析结果不能作为 key 的值而字符串可以时
+/// （例如 `LUOGU_PAINT_COOKIE_DIR=2024`）视为字符串
+fn parse_env_value(key: &str, raw: &str) -> toml::Value {
+    #[derive(Deserialize)]
+    struct Wrapper {
+        value: toml::Value,
+    }
+    let raw_value = toml::Value::String(raw.to_string());
+    match toml::from_str::<Wrapper>(&format!("value = {}", raw)) {
+        Ok(wrapper) if wrapper.value.is_str() || accepts(key, &wrapper.value) => wrapper.value,
+        Ok(wrapper) if !accepts(key, &raw_value) => wrapper.value,
+        _ => raw_value,
+    }
+}
+
+/// 在默认配置中将 key 设为 value 后能否得到合法的 Config
+fn accepts(key: &str, value: &toml::Value) -> bool {
+    let mut table = match toml::Value::try_from(Config::default()) {
+        Ok(toml::Value::Table(table)) => table,
+        _ => return true,
+    };
+    set_value(&mut table, key, value.clone());
+    toml::Value::Table(table).try_into::<Config>().is_ok()
+}
+
+/// 按 默认值 < 配置文件 < 环境变量 < 命令行 的顺序合并配置，并记录每一项的来源
+#[derive(Default)]
+pub struct ConfigLayers {
+    file: toml::value::Table,
+    env: Vec<(String, Override)>,
+    cli: Vec<Override>,
+}
+
+impl ConfigLayers {
+    pub fn new() -> ConfigLayers {
+        ConfigLayers::default()
+    }
+
+    /// 读取配置文件
+    pub fn file<T>(self, filename: T) -> Result<ConfigLayers, ScriptError>
+    where
+        T: AsRef<std::path::Path>,
+    {
+        self.toml(&std::fs::read_to_string(filename.as_ref())?)
+    }
+    pub fn toml(mut self, raw_config: &str) -> Result<ConfigLayers, ScriptError> {
+        self.file = toml::from_str(raw_config)?;
+        Ok(self)
+    }
+
+    /// 读取以 `LUOGU_PAINT_` 开头的环境变量，例如 `std::env::vars()`
+    pub fn env<I>(mut self, vars: I) -> ConfigLayers
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, raw) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                let key = key.to_lowercase().replace("__", ".");
+                let value = parse_env_value(&key, &raw);
+                self.env.push((name.clone(), (key, value)));
+            }
+        }
+        // 保证结果与环境变量的顺序无关
+        self.env.sort_by(|a, b| a.0.cmp(&b.0));
+        self
+    }
+
+    pub fn cli(mut self, overrides: &[Override]) -> ConfigLayers {
+        self.cli.extend_from_slice(overrides);
+        self
+    }
+
+    /// 合并所有来源并检查配置
+    pub fn build(&self) -> Result<Config, ScriptError> {
+        let mut table = self.file.clone();
+        let overrides = self
+            .env
+            .iter()
+            .map(|(_, value)| value)
+            .chain(self.cli.iter());
+        for (key, value) in overrides {
+            set_value(&mut table, key, value.clone());
+        }
+        let config: Config = toml::Value::Table(table).try_into()?;
+        config.check()?;
         Ok(config)
     }
+
+    /// key 的值来自哪一层，表中的项也受整个表的覆盖影响
+    pub fn source(&self, key: &str) -> ConfigSource {
+        let covers = |layer_key: &str| {
+            key == layer_key
+                || key.starts_with(&format!("{}.", layer_key))
+                || layer_key.starts_with(&format!("{}.", key))
+        };
+        if self.cli.iter().any(|(layer_key, _)| covers(layer_key)) {
+            return ConfigSource::Cli;
+        }
+        if let Some((name, _)) = self
+            .env
+            .iter()
+            .rev()
+            .find(|(_, (layer_key, _))| covers(layer_key))
+        {
+            return ConfigSource::Env(name.clone());
+        }
+        let mut file = BTreeMap::new();
+        flatten("", &self.file, &mut file);
+        if file.keys().any(|layer_key| covers(layer_key)) {
+            return ConfigSource::File;
+        }
+        ConfigSource::Default
+    }
+
+    /// 以 `key = value  # 来源` 的形式列出 config 的每一项
+    pub fn dump(&self, config: &Config) -> Result<String, ScriptError> {
+        let mut values = BTreeMap::new();
+        if let toml::Value::Table(table) = toml::Value::try_from(config)? {
+            flatten("", &table, &mut values);
+        }
+        Ok(values
+            .iter()
+            .map(|(key, value)| format!("{} = {}  # {}\n", key, inline(value), self.source(key)))
+            .collect())
+    }
+}
+
+impl Config {
+    /// 后台线程数：刷新画板、WebSocket，以及启用时重新读取 Cookie 和绘图数据
+    pub fn background_workers(&self) -> usize {
+        2 + usize::from(self.cookie_reload_interval > 0)
+            + usize::from(self.node_reload_interval > 0)
+    }
+
+    /// 不涉及文件的检查，合并配置时执行
     fn check(&self) -> Result<(), ScriptError> {
+        into_result(self.static_problems())
+    }
+
+    /// 开始绘制前完整检查配置，包括线程数是否足够、cookie_dir 与 node_file 能否读取、
+    /// 绘图数据是否超出画板，一次返回所有问题
+    pub fn validate(&self) -> Result<(), ScriptError> {
+        let mut problems = self.static_problems();
+        problems.extend(self.daemon_problems());
+        into_result(problems)
+    }
+
+    fn static_problems(&self) -> Vec<ConfigError> {
+        let mut problems = Vec::new();
         // check if the board_addr and websocket_addr is what we are expected
         // to avoid fill in a http URL in websocket_addr
-        let board_addr = Url::parse(&self.board_addr)?;
-        let websocket_addr = Url::parse(&self.websocket_addr)?;
-        if board_addr.scheme() != "http" && board_addr.scheme() != "https" {
-            return Err(ScriptError::UnexpectedUrl(UrlError::InvalidHTTPUrl));
+        let addresses: [(&'static str, &str, &[&str], UrlError); 2] = [
+            (
+                "board_addr",
+                &self.board_addr,
+                &["http", "https"],
+                UrlError::InvalidHTTPUrl,
+            ),
+            (
+                "websocket_addr",
+                &self.websocket_addr,
+                &["ws", "wss"],
+                UrlError::InvalidWSUrl,
+            ),
+        ];
+        for (key, addr, schemes, error) in addresses {
+            match Url::parse(addr) {
+                Ok(url) if schemes.contains(&url.scheme()) => {}
+                Ok(_) => problems.push(ConfigError::InvalidAddress(key, error.into())),
+                Err(err) => problems.push(ConfigError::InvalidAddress(key, err.into())),
+            }
         }
-        if websocket_addr.scheme() != "ws" && websocket_addr.scheme() != "wss" {
-            return Err(ScriptError::UnexpectedUrl(UrlError::InvalidWSUrl));
+        if let Err(err) = self.palette.check() {
+            problems.push(ConfigError::InvalidPalette(err));
         }
-        Ok(())
+        if self.board_width == 0 || self.board_height == 0 {
+            problems.push(ConfigError::EmptyBoard {
+                width: self.board_width,
+                height: self.board_height,
+            });
+        }
+        problems
+    }
+
+    /// 只有开始绘制时才需要的检查
+    fn daemon_problems(&self) -> Vec<ConfigError> {
+        let mut problems = Vec::new();
+        let workers = self.background_workers();
+        if self.thread_num <= workers {
+            problems.push(ConfigError::TooFewThreads {
+                thread_num: self.thread_num,
+                workers,
+            });
+        }
+        match std::fs::metadata(&self.cookie_dir) {
+            Ok(metadata) if !metadata.is_dir() => {
+                problems.push(ConfigError::NotADirectory(self.cookie_dir.clone()))
+            }
+            Ok(_) => {
+                if let Err(err) = std::fs::read_dir(&self.cookie_dir) {
+                    problems.push(ConfigError::Unreadable(
+                        "cookie_dir",
+                        self.cookie_dir.clone(),
+                        err,
+                    ));
+                }
+            }
+            Err(err) => problems.push(ConfigError::Unreadable(
+                "cookie_dir",
+                self.cookie_dir.clone(),
+                err,
+            )),
+        }
+        let nodes = match std::fs::File::open(&self.node_file) {
+            Ok(_) => init::get_node(&self.node_file, &self.palette),
+            Err(err) => {
+                problems.push(ConfigError::Unreadable(
+                    "node_file",
+                    self.node_file.clone(),
+                    err,
+                ));
+                return problems;
+            }
+        };
+        match nodes {
+            Ok(nodes) => {
+                let mut outside = nodes
+                    .iter()
+                    .filter(|node| node.x >= self.board_width || node.y >= self.board_height);
+                if let Some(first) = outside.next() {
+                    problems.push(ConfigError::NodeOutOfBounds {
+                        count: outside.count() + 1,
+                        x: first.x,
+                        y: first.y,
+                    });
+                }
+            }
+            Err(err) => problems.push(ConfigError::InvalidNodeFile(err)),
+        }
+        problems
     }
 
     pub fn new<T>(filename: T) -> Result<Config, ScriptError>
     where
         T: AsRef<std::path::Path>,
     {
-        let config = Config::from_toml(&std::fs::read_to_string(&filename.as_ref())?)?;
-        config.check()?;
-        Ok(config)
+        Config::with_overrides(filename, &[])
+    }
+
+    /// 读取配置文件，并用 overrides 覆盖其中的项
+    pub fn with_overrides<T>(filename: T, overrides: &[Override]) -> Result<Config, ScriptError>
+    where
+        T: AsRef<std::path::Path>,
+    {
+        ConfigLayers::new().file(filename)?.cli(overrides).build()
+    }
+}
+
+fn into_result(problems: Vec<ConfigError>) -> Result<(), ScriptError> {
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ScriptError::InvalidConfig(problems))
     }
 }
 
@@ -49,24 +489,123 @@ impl Config {
 mod tests {
     use super::*;
 
+    #[test]
+    fn override_values() {
+        let raw_config = r#"
+            board_addr = "http://127.0.0.1"
+            websocket_addr = "ws://127.0.0.1"
+            cookie_dir = "cookies"
+            node_file = "node.json"
+            wait_time = 30
+            thread_num = 5
+            board_width = 10
+            board_height = 10
+
+            [rate_limit]
+            burst = 3
+            "#;
+        let config = ConfigLayers::new()
+            .toml(raw_config)
+            .unwrap()
+            .cli(&[
+                ("thread_num".to_string(), toml::Value::Integer(8)),
+                (
+                    "rate_limit.requests_per_second".to_string(),
+                    toml::Value::Float(0.5),
+                ),
+                (
+                    "http.proxy".to_string(),
+                    toml::Value::String("socks5://127.0.0.1:1080".to_string()),
+                ),
+            ])
+            .build()
+            .unwrap();
+        assert_eq!(config.thread_num, 8);
+        assert_eq!(config.wait_time, 30);
+        assert_eq!(config.rate_limit.requests_per_second, 0.5);
+        assert_eq!(config.rate_limit.burst, 3);
+        assert_eq!(
+            config.http.proxy.as_deref(),
+            Some("socks5://127.0.0.1:1080")
+        );
+    }
+
+    #[test]
+    fn config_layers() {
+        let layers = ConfigLayers::new()
+            .toml(
+                r#"
+                thread_num = 5
+                wait_time = 10
+
+                [rate_limit]
+                burst = 3
+                "#,
+            )
+            .unwrap()
+            .env(vec![
+                ("LUOGU_PAINT_WAIT_TIME".to_string(), "20".to_string()),
+                ("LUOGU_PAINT_THREAD_NUM".to_string(), "6".to_string()),
+                (
+                    "LUOGU_PAINT_BOARD_ADDR".to_string(),
+                    "http://127.0.0.1:8080".to_string(),
+                ),
+                (
+                    "LUOGU_PAINT_RATE_LIMIT__REQUESTS_PER_SECOND".to_string(),
+                    "0.5".to_string(),
+                ),
+                ("PATH".to_string(), "/bin".to_string()),
+                // 字符串配置项的值不按 TOML 的类型解析
+                ("LUOGU_PAINT_COOKIE_DIR".to_string(), "2024".to_string()),
+                ("LUOGU_PAINT_NODE_FILE".to_string(), "true".to_string()),
+                (
+                    "LUOGU_PAINT_BOARD_CACHE".to_string(),
+                    "2024-01-01".to_string(),
+                ),
+            ])
+            .cli(&[("thread_num".to_string(), toml::Value::Integer(7))]);
+        let config = layers.build().unwrap();
+        assert_eq!(config.thread_num, 7);
+        assert_eq!(config.wait_time, 20);
+        assert_eq!(config.board_addr, "http://127.0.0.1:8080");
+        assert_eq!(config.rate_limit.requests_per_second, 0.5);
+        assert_eq!(config.rate_limit.burst, 3);
+        assert_eq!(config.board_width, 1000);
+        assert_eq!(config.cookie_dir, "2024");
+        assert_eq!(config.node_file, "true");
+        assert_eq!(config.board_cache.as_deref(), Some("2024-01-01"));
+
+        assert_eq!(layers.source("thread_num"), ConfigSource::Cli);
+        assert_eq!(
+            layers.source("wait_time"),
+            ConfigSource::Env("LUOGU_PAINT_WAIT_TIME".to_string())
+        );
+        assert_eq!(layers.source("rate_limit.burst"), ConfigSource::File);
+        assert_eq!(layers.source("board_width"), ConfigSource::Default);
+
+        let dump = layers.dump(&config).unwrap();
+        assert!(dump.contains("thread_num = 7  # command line\n"));
+        assert!(dump.contains("rate_limit.burst = 3  # file\n"));
+    }
+
     #[test]
     fn check_board_address() {
         let config = Config {
             board_addr: "ws://qwq.com".to_string(),
             websocket_addr: "wss://qwq.com".to_string(),
-            cookie_dir: "/home".to_string(),
-            node_file: "/home/node.file".to_string(),
-            wait_time: 30,
-            thread_num: 8,
-            board_width: 1000,
-            board_height: 600,
+            ..Config::default()
         };
 
-        if let Err(ScriptError::UnexpectedUrl(UrlError::InvalidHTTPUrl)) = config.check() {
-            assert!(true);
-        } else {
-            assert!(false);
-        }
+        assert!(matches!(
+            config.check(),
+            Err(ScriptError::InvalidConfig(problems)) if matches!(
+                problems[..],
+                [ConfigError::InvalidAddress(
+                    "board_addr",
+                    ScriptError::UnexpectedUrl(UrlError::InvalidHTTPUrl)
+                )]
+            )
+        ));
     }
 
     #[test]
@@ -74,18 +613,129 @@ mod tests {
         let config = Config {
             board_addr: "http://qwq.com".to_string(),
             websocket_addr: "https://qwq.com".to_string(),
-            cookie_dir: "/home".to_string(),
-            node_file: "/home/node.file".to_string(),
-            wait_time: 30,
-            thread_num: 8,
-            board_width: 1000,
-            board_height: 600,
+            ..Config::default()
         };
 
-        if let Err(ScriptError::UnexpectedUrl(UrlError::InvalidWSUrl)) = config.check() {
-            assert!(true);
-        } else {
-            assert!(false);
-        }
+        assert!(matches!(
+            config.check(),
+            Err(ScriptError::InvalidConfig(problems)) if matches!(
+                problems[..],
+                [ConfigError::InvalidAddress(
+                    "websocket_addr",
+                    ScriptError::UnexpectedUrl(UrlError::InvalidWSUrl)
+                )]
+            )
+        ));
+    }
+
+    #[test]
+    fn report_all_problems() {
+        let config = Config {
+            websocket_addr: "https://qwq.com".to_string(),
+            thread_num: 4,
+            board_height: 0,
+            ..Config::default()
+        };
+        let problems = match config.check() {
+            Err(ScriptError::InvalidConfig(problems)) => problems,
+            _ => panic!("expected invalid config"),
+        };
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(
+            problems[0],
+            ConfigError::InvalidAddress("websocket_addr", _)
+        ));
+        assert!(matches!(
+            problems[1],
+            ConfigError::EmptyBoard {
+                width: 1000,
+                height: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn check_threads() {
+        let too_few_threads = |config: &Config| match config.validate() {
+            Err(ScriptError::InvalidConfig(problems)) => problems
+                .into_iter()
+                .find(|problem| matches!(problem, ConfigError::TooFewThreads { .. })),
+            _ => None,
+        };
+        // 线程数只影响绘制，其他命令不检查
+        let config = Config {
+            thread_num: 3,
+            ..Config::default()
+        };
+        assert!(config.check().is_ok());
+        assert!(matches!(
+            too_few_threads(&config),
+            Some(ConfigError::TooFewThreads {
+                thread_num: 3,
+                workers: 4
+            })
+        ));
+
+        let config = Config {
+            thread_num: 3,
+            cookie_reload_interval: 0,
+            node_reload_interval: 0,
+            ..Config::default()
+        };
+        assert!(too_few_threads(&config).is_none());
+    }
+
+    #[test]
+    fn validate_files() {
+        let dir = std::env::temp_dir().join(format!("draw-script-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let node_file = dir.join("node.json");
+        std::fs::write(&node_file, "[[0, 0, 1], [10, 2, 1], [3, 20, 1]]").unwrap();
+        let config = Config {
+            cookie_dir: dir.join("missing").to_string_lossy().into_owned(),
+            node_file: node_file.to_string_lossy().into_owned(),
+            board_width: 10,
+            board_height: 10,
+            ..Config::default()
+        };
+        assert!(config.check().is_ok());
+        let problems = match config.validate() {
+            Err(ScriptError::InvalidConfig(problems)) => problems,
+            _ => panic!("expected invalid config"),
+        };
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(
+            problems[0],
+            ConfigError::Unreadable("cookie_dir", _, _)
+        ));
+        assert!(matches!(
+            problems[1],
+            ConfigError::NodeOutOfBounds { count: 2, .. }
+        ));
+
+        let config = Config {
+            cookie_dir: node_file.to_string_lossy().into_owned(),
+            node_file: dir.join("missing.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let problems = match config.validate() {
+            Err(ScriptError::InvalidConfig(problems)) => problems,
+            _ => panic!("expected invalid config"),
+        };
+        assert!(matches!(
+            problems[..],
+            [
+                ConfigError::NotADirectory(_),
+                ConfigError::Unreadable("node_file", _, _)
+            ]
+        ));
+
+        let config = Config {
+            cookie_dir: dir.to_string_lossy().into_owned(),
+            node_file: node_file.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,200 @@
+use crate::{node::NodeOpt, palette::Palette, ScriptError};
+
+use std::collections::VecDeque;
+
+use image::DynamicImage;
+
+/// alpha 低于该值的像素视为透明，不会成为绘制目标
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// 4x4 Bayer 矩阵
+const BAYER_MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+/// Bayer 抖动时阈值在每个通道上的扰动幅度
+const BAYER_SPREAD: f64 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 将像素映射到调色板的策略
+pub enum Quantizer {
+    /// RGB 空间最近颜色
+    #[default]
+    Nearest,
+    /// CIELAB 空间中 CIEDE2000 色差最小的颜色
+    Ciede2000,
+    /// Floyd–Steinberg 误差扩散抖动
+    FloydSteinberg,
+    /// 4x4 Bayer 有序抖动
+    Bayer,
+}
+
+impl std::str::FromStr for Quantizer {
+    type Err = ScriptError;
+
+    fn from_str(name: &str) -> Result<Quantizer, ScriptError> {
+        match name {
+            "nearest" => Ok(Quantizer::Nearest),
+            "ciede2000" => Ok(Quantizer::Ciede2000),
+            "floyd-steinberg" => Ok(Quantizer::FloydSteinberg),
+            "bayer" => Ok(Quantizer::Bayer),
+            _ => Err(ScriptError::UnknownQuantizer(name.to_string())),
+        }
+    }
+}
+
+fn clamp_rgb(rgb: [f64; 3]) -> [u8; 3] {
+    let clamp = |c: f64| c.round().clamp(0.0, 255.0) as u8;
+    [clamp(rgb[0]), clamp(rgb[1]), clamp(rgb[2])]
+}
+
+/// 将图片放置在 (offset_x, offset_y) 处，转换为绘制目标
+pub fn image_to_node(
+    image: &DynamicImage,
+    offset_x: usize,
+    offset_y: usize,
+    palette: &Palette,
+    quantizer: Quantizer,
+) -> VecDeque<NodeOpt> {
+    let image = image.to_rgba8();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    // Floyd–Steinberg 累积的误差
+    let mut error = vec![[0f64; 3]; width * height];
+    let mut queue = VecDeque::new();
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b, a] = image.get_pixel(x as u32, y as u32).0;
+            if a < ALPHA_THRESHOLD {
+                continue;
+            }
+            let rgb = [f64::from(r), f64::from(g), f64::from(b)];
+            let color = match quantizer {
+                Quantizer::Nearest => palette.nearest([r, g, b]),
+                Quantizer::Ciede2000 => palette.nearest_lab([r, g, b]),
+                Quantizer::Bayer => {
+                    let threshold = f64::from(BAYER_MATRIX[y % 4][x % 4]) / 16.0 - 0.5;
+                    let offset = threshold * BAYER_SPREAD;
+                    palette.nearest_lab(clamp_rgb([
+                        rgb[0] + offset,
+                        rgb[1] + offset,
+                        rgb[2] + offset,
+                    ]))
+                }
+                Quantizer::FloydSteinberg => {
+                    let diffused = error[y * width + x];
+                    let wanted = [
+                        rgb[0] + diffused[0],
+                        rgb[1] + diffused[1],
+                        rgb[2] + diffused[2],
+                    ];
+                    let color = palette.nearest_lab(clamp_rgb(wanted));
+                    let actual = palette.rgb(color).unwrap_or([r, g, b]);
+                    let diff = [
+                        wanted[0] - f64::from(actual[0]),
+                        wanted[1] - f64::from(actual[1]),
+                        wanted[2] - f64::from(actual[2]),
+                    ];
+                    let mut spread = |dx: isize, dy: usize, weight: f64| {
+                        let nx = x as isize + dx;
+                        let ny = y + dy;
+                        if nx < 0 || nx as usize >= width || ny >= height {
+                            return;
+                        }
+                        let cell = &mut error[ny * width + nx as usize];
+                        for channel in 0..3 {
+                            cell[channel] += diff[channel] * weight;
+                        }
+                    };
+                    spread(1, 0, 7.0 / 16.0);
+                    spread(-1, 1, 3.0 / 16.0);
+                    spread(0, 1, 5.0 / 16.0);
+                    spread(1, 1, 1.0 / 16.0);
+                    color
+                }
+            };
+            queue.push_back(NodeOpt {
+                x: offset_x + x,
+                y: offset_y + y,
+                color,
+            });
+        }
+    }
+    queue
+}
+
+/// 读取 PNG/BMP/PPM 等格式的图片文件并转换为绘制目标
+pub fn get_node_from_image<T>(
+    file: &T,
+    offset_x: usize,
+    offset_y: usize,
+    palette: &Palette,
+    quantizer: Quantizer,
+) -> Result<VecDeque<NodeOpt>, ScriptError>
+where
+    T: AsRef<std::path::Path>,
+{
+    let image = image::open(file.as_ref())?;
+    Ok(image_to_node(
+        &image, offset_x, offset_y, palette, quantizer,
+    ))
+}
+
+/// 将绘制目标写入 `init::get_node` 可读取的 JSON 文件
+pub fn save_node<T>(file: &T, nodes: &VecDeque<NodeOpt>) -> Result<(), ScriptError>
+where
+    T: AsRef<std::path::Path>,
+{
+    let dot_draw: Vec<[usize; 3]> = nodes
+        .iter()
+        .map(|node| [node.x, node.y, node.color])
+        .collect();
+    std::fs::write(file.as_ref(), serde_json::to_string(&dot_draw)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn convert_with_offset_and_transparency() {
+        let mut image = RgbaImage::new(2, 2);
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([250, 250, 250, 255]));
+        image.put_pixel(0, 1, Rgba([240, 70, 50, 255]));
+        image.put_pixel(1, 1, Rgba([255, 255, 255, 0]));
+
+        let nodes = image_to_node(
+            &DynamicImage::ImageRgba8(image),
+            10,
+            20,
+            &Palette::default(),
+            Quantizer::Nearest,
+        );
+        let nodes: Vec<(usize, usize, usize)> = nodes
+            .iter()
+            .map(|node| (node.x, node.y, node.color))
+            .collect();
+
+        assert_eq!(nodes, vec![(10, 20, 0), (11, 20, 1), (10, 21, 8)]);
+    }
+
+    #[test]
+    fn dithering_mixes_gray() {
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]]);
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([128, 128, 128, 255])));
+
+        for quantizer in [Quantizer::FloydSteinberg, Quantizer::Bayer].iter() {
+            let nodes = image_to_node(&image, 0, 0, &palette, *quantizer);
+            let white = nodes.iter().filter(|node| node.color == 1).count();
+            assert!(
+                white > 16 && white < 48,
+                "{:?} gives {} white",
+                quantizer,
+                white
+            );
+        }
+        let nodes = image_to_node(&image, 0, 0, &palette, Quantizer::Nearest);
+        assert!(nodes.iter().all(|node| node.color == 1));
+    }
+}
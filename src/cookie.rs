@@ -1,21 +1,279 @@
-use super::Config;
+use super::daemon::Shutdown;
+use super::ratelimit::{cooldown_remaining, Clock, SystemClock};
+use super::{write_atomic, Config, ScriptError};
 
-use std::collections::VecDeque;
-use std::sync::Mutex;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 /// 原始 Cookie
 pub struct RawCookie {
     pub cookie: String,
+    /// 该 Cookie 的冷却时间（秒），默认使用配置中的 `wait_time`
+    #[serde(default)]
+    pub wait_time: Option<u64>,
+    /// 优先级，有多个 Cookie 冷却完毕时优先使用较大的，默认为 1
+    #[serde(default)]
+    pub weight: Option<u32>,
 }
 
-#[derive(Debug)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+/// 洛谷的用户编号，导出的表格中可能是数字也可能是字符串
+enum Uid {
+    Number(u64),
+    String(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+/// JSON 格式的 Cookie 文件中的一项
+enum CookieEntry {
+    Raw(String),
+    Cookie(RawCookie),
+    Pair {
+        uid: Uid,
+        client_id: String,
+        #[serde(default)]
+        wait_time: Option<u64>,
+        #[serde(default)]
+        weight: Option<u32>,
+    },
+}
+
+impl From<CookieEntry> for RawCookie {
+    fn from(entry: CookieEntry) -> RawCookie {
+        match entry {
+            CookieEntry::Raw(cookie) => RawCookie::new(cookie),
+            CookieEntry::Cookie(cookie) => cookie,
+            CookieEntry::Pair {
+                uid,
+                client_id,
+                wait_time,
+                weight,
+            } => {
+                let uid = match uid {
+                    Uid::Number(uid) => uid.to_string(),
+                    Uid::String(uid) => uid,
+                };
+                RawCookie {
+                    wait_time,
+                    weight,
+                    ..RawCookie::from_pair(&uid, &client_id)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CookieFile {
+    One(CookieEntry),
+    Many(Vec<CookieEntry>),
+}
+
+impl RawCookie {
+    pub fn new(cookie: String) -> RawCookie {
+        RawCookie {
+            cookie,
+            wait_time: None,
+            weight: None,
+        }
+    }
+    pub fn from_pair(uid: &str, client_id: &str) -> RawCookie {
+        RawCookie::new(format!(
+            "_uid={};__client_id={}",
+            uid.trim(),
+            client_id.trim()
+        ))
+    }
+}
+
+/// 解析一个 Cookie 文件，支持以下格式：
+/// - JSON：`{"cookie": "..."}`、`{"uid": ..., "client_id": "..."}`、字符串，或它们组成的数组，
+///   对象中可以用 `wait_time` 与 `weight` 覆盖冷却时间与优先级
+/// - Netscape 格式的 `cookies.txt`，按域名组合 `_uid` 与 `__client_id`
+/// - 纯文本，每行一个 Cookie，`#` 开头的行为注释
+pub fn parse_cookie_file(content: &str) -> Result<Vec<RawCookie>, ScriptError> {
+    let content = content.trim_start_matches('\u{feff}').trim();
+    let cookies = if content.starts_with('{') || content.starts_with('[') {
+        match serde_json::from_str(content)? {
+            CookieFile::One(entry) => vec![entry.into()],
+            CookieFile::Many(entries) => entries.into_iter().map(RawCookie::from).collect(),
+        }
+    } else if is_netscape(content) {
+        parse_netscape(content)?
+    } else {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| RawCookie::new(line.to_string()))
+            .collect()
+    };
+    if cookies.is_empty() {
+        return Err(ScriptError::InvalidCookieFile("no cookie found"));
+    }
+    Ok(cookies)
+}
+
+/// Netscape 格式中 HttpOnly 的 Cookie 以此为前缀，并不是注释
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+fn netscape_fields(line: &str) -> Option<Vec<&str>> {
+    let line = line.strip_prefix(HTTP_ONLY_PREFIX).unwrap_or(line);
+    if line.starts_with('#') {
+        return None;
+    }
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() == 7 {
+        Some(fields)
+    } else {
+        None
+    }
+}
+
+fn is_netscape(content: &str) -> bool {
+    content.starts_with("# Netscape HTTP Cookie File")
+        || content.starts_with("# HTTP Cookie File")
+        || content.lines().any(|line| netscape_fields(line).is_some())
+}
+
+fn parse_netscape(content: &str) -> Result<Vec<RawCookie>, ScriptError> {
+    use std::collections::BTreeMap;
+
+    // 域名 -> (_uid, __client_id)
+    let mut domains: BTreeMap<&str, (Option<&str>, Option<&str>)> = BTreeMap::new();
+    for line in content.lines() {
+        let fields = match netscape_fields(line.trim_end_matches('\r')) {
+            Some(fields) => fields,
+            None => continue,
+        };
+        let domain = fields[0].trim_start_matches('.');
+        let entry = domains.entry(domain).or_default();
+        match fields[5] {
+            "_uid" => entry.0 = Some(fields[6]),
+            "__client_id" => entry.1 = Some(fields[6]),
+            _ => {}
+        }
+    }
+    let cookies: Vec<RawCookie> = domains
+        .values()
+        .filter_map(|pair| match pair {
+            (Some(uid), Some(client_id)) => Some(RawCookie::from_pair(uid, client_id)),
+            _ => None,
+        })
+        .collect();
+    if cookies.is_empty() {
+        return Err(ScriptError::InvalidCookieFile(
+            "cookies.txt contains no _uid and __client_id pair",
+        ));
+    }
+    Ok(cookies)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+/// 需要在重启后保留的 Cookie 状态
+pub struct CookieState {
+    /// 上次绘制成功的时间，Unix 时间戳（毫秒）
+    pub last_success: Option<u64>,
+    pub successes: u64,
+    pub failures: u64,
+    /// 自上次成功以来连续失败的次数
+    pub consecutive_failures: u32,
+    /// 已被判定失效，不再使用
+    pub disabled: bool,
+}
+
+impl CookieState {
+    /// 距离上次绘制成功经过的时间
+    fn since_last_success(&self, now: SystemTime) -> Option<Duration> {
+        let last_success = UNIX_EPOCH + Duration::from_millis(self.last_success?);
+        Some(now.duration_since(last_success).unwrap_or_default())
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+/// 配置文件中的 `[cookie_backoff]`
+pub struct CookieBackoffConfig {
+    /// 连续失败多少次后暂停使用该 Cookie，0 表示从不暂停
+    pub failures: u32,
+    /// 第一次暂停的时间（秒），之后每次连续失败翻倍
+    pub initial: u64,
+    /// 暂停时间的上限（秒）
+    pub max: u64,
+}
+
+impl Default for CookieBackoffConfig {
+    fn default() -> CookieBackoffConfig {
+        CookieBackoffConfig {
+            failures: 3,
+            initial: 60,
+            max: 3600,
+        }
+    }
+}
+
+impl CookieBackoffConfig {
+    /// 连续失败 consecutive_failures 次后需要暂停的时间
+    pub fn bench_time(&self, consecutive_failures: u32) -> Option<Duration> {
+        if self.failures == 0 || consecutive_failures < self.failures {
+            return None;
+        }
+        let exponent = (consecutive_failures - self.failures).min(31);
+        let secs = self.initial.saturating_mul(1 << exponent).min(self.max);
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// 单个 Cookie 的统计信息
+#[derive(Debug, Clone)]
+pub struct CookieStats {
+    pub cookie: String,
+    pub source: Option<PathBuf>,
+    pub state: CookieState,
+    /// 因连续失败被暂停时，距离恢复使用的时间
+    pub benched: Option<Duration>,
+}
+
+/// 读取 Cookie 状态文件，文件不存在时返回空表
+pub fn load_state<T>(path: &T) -> Result<BTreeMap<String, CookieState>, ScriptError>
+where
+    T: AsRef<Path>,
+{
+    match std::fs::read_to_string(path.as_ref()) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Debug, Clone)]
 /// 提供了时间检测的 Cookie
 pub struct Cookie {
     cookie: String,
     last_update: std::time::Instant,
+    /// 读取该 Cookie 的文件
+    source: Option<std::path::PathBuf>,
+    state: CookieState,
+    /// 连续失败后暂停使用直到该时间
+    benched_until: Option<std::time::Instant>,
+    /// 覆盖配置中的 `wait_time`
+    wait_time: Option<u64>,
+    weight: u32,
 }
 
 impl Cookie {
@@ -23,55 +281,635 @@ impl Cookie {
         Cookie {
             cookie: raw_cookie.cookie,
             last_update: std::time::Instant::now(),
+            source: None,
+            state: CookieState::default(),
+            benched_until: None,
+            wait_time: raw_cookie.wait_time,
+            weight: raw_cookie.weight.unwrap_or(1),
+        }
+    }
+    pub fn with_source(raw_cookie: RawCookie, source: std::path::PathBuf) -> Cookie {
+        Cookie {
+            source: Some(source),
+            ..Cookie::new(raw_cookie)
         }
     }
 
     pub fn cookie(&self) -> &str {
         &self.cookie
     }
+    pub fn source(&self) -> Option<&std::path::Path> {
+        self.source.as_deref()
+    }
     pub fn last_update(&self) -> std::time::Instant {
         self.last_update
     }
+    pub fn state(&self) -> &CookieState {
+        &self.state
+    }
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+    /// 该 Cookie 的冷却时间，未单独设置时使用 wait_time
+    pub fn cooldown(&self, wait_time: u64) -> Duration {
+        Duration::from_secs(self.wait_time.unwrap_or(wait_time))
+    }
 
     pub fn update(&mut self) {
-        self.last_update = std::time::Instant::now();
+        self.update_at(std::time::Instant::now());
+    }
+    /// 记录该 Cookie 在 last_update 时被使用过，例如启动前的检测
+    pub fn update_at(&mut self, last_update: std::time::Instant) {
+        self.last_update = last_update;
+    }
+
+    /// 冷却完毕且未被暂停的时间
+    fn ready_at(&self, wait_time: u64) -> std::time::Instant {
+        let ready = self.last_update + self.cooldown(wait_time);
+        match self.benched_until {
+            Some(benched_until) if benched_until > ready => benched_until,
+            _ => ready,
+        }
     }
 }
 
 /// Cookies 列表
+/// 每次取出最早冷却完毕的 Cookie，等待时不会占用锁
 pub struct CookieList {
-    list: Mutex<VecDeque<Cookie>>,
+    list: Mutex<Vec<Cookie>>,
+    /// 列表发生变化时唤醒等待的线程
+    changed: Condvar,
+    /// 已被判定失效的 Cookie，重新读取目录时不再加入
+    removed: Mutex<HashMap<String, CookieState>>,
+    /// 避免多个线程同时写入状态文件
+    saving: Mutex<()>,
+    clock: Arc<dyn Clock>,
 }
 
 impl CookieList {
     pub fn new(list: VecDeque<Cookie>) -> CookieList {
+        CookieList::with_clock(list, Arc::new(SystemClock))
+    }
+    pub fn with_clock(list: VecDeque<Cookie>, clock: Arc<dyn Clock>) -> CookieList {
         CookieList {
-            list: Mutex::new(list),
+            list: Mutex::new(list.into()),
+            changed: Condvar::new(),
+            removed: Mutex::new(HashMap::new()),
+            saving: Mutex::new(()),
+            clock,
         }
     }
-    pub fn get_cookie(&self, config: &Config) -> String {
+    /// 取出一个已冷却的 Cookie，收到停止信号时返回 None
+    /// 有多个 Cookie 冷却完毕时取 weight 最大的，相同时取最早冷却完毕的
+    pub fn get_cookie(
+        &self,
+        config: &Config,
+        shutdown: &Shutdown,
+    ) -> Result<Option<String>, ScriptError> {
+        let wait_time = config.wait_time;
         let mut list = self.list.lock().unwrap();
-        let mut cur_cookie = list.pop_front().unwrap();
-
-        while std::time::Instant::now() - cur_cookie.last_update()
-            <= std::time::Duration::from_secs(config.wait_time)
-        {
-            std::thread::sleep(std::time::Duration::from_secs(1));
+        loop {
+            if shutdown.is_triggered() {
+                return Ok(None);
+            }
+            let now = self.clock.now();
+            let ready = list
+                .iter_mut()
+                .filter(|cookie| cookie.ready_at(wait_time) <= now)
+                .min_by(|a, b| {
+                    b.weight
+                        .cmp(&a.weight)
+                        .then_with(|| a.ready_at(wait_time).cmp(&b.ready_at(wait_time)))
+                });
+            if let Some(cur_cookie) = ready {
+                cur_cookie.last_update = now;
+                cur_cookie.benched_until = None;
+                return Ok(Some(cur_cookie.cookie().to_string()));
+            }
+            let next_ready = list
+                .iter()
+                .map(|cookie| cookie.ready_at(wait_time))
+                .min()
+                .ok_or(ScriptError::NoCookieAvailable)?;
+            let remaining = cooldown_remaining(next_ready, now, Duration::ZERO).unwrap_or_default();
+            list = self.changed.wait_timeout(list, remaining).unwrap().0;
         }
-
-        cur_cookie.update();
-        let cookie = cur_cookie.cookie().to_string();
-        list.push_back(cur_cookie);
-
-        cookie
     }
     pub fn remove_cookie(&self, cookie: &str) {
+        let mut removed = self.removed.lock().unwrap();
+        let mut list = self.list.lock().unwrap();
+        let mut state = match list.iter().position(|cur| cur.cookie == cookie) {
+            Some(pos) => list.swap_remove(pos).state,
+            None => CookieState::default(),
+        };
+        state.disabled = true;
+        removed.insert(cookie.to_string(), state);
+        self.changed.notify_all();
+    }
+    /// 记录一次成功的绘制
+    pub fn record_success(&self, cookie: &str) {
+        let mut list = self.list.lock().unwrap();
+        if let Some(cur) = list.iter_mut().find(|cur| cur.cookie == cookie) {
+            cur.state.successes += 1;
+            cur.state.consecutive_failures = 0;
+            cur.state.last_success = Some(unix_millis(SystemTime::now()));
+        }
+    }
+    /// 记录一次因 Cookie 导致的失败，连续失败过多时暂停使用该 Cookie
+    pub fn record_failure(&self, cookie: &str, config: &Config) {
+        let mut list = self.list.lock().unwrap();
+        if let Some(cur) = list.iter_mut().find(|cur| cur.cookie == cookie) {
+            cur.state.failures += 1;
+            cur.state.consecutive_failures += 1;
+            if let Some(bench_time) = config
+                .cookie_backoff
+                .bench_time(cur.state.consecutive_failures)
+            {
+                log::warn!(
+                    "Cookie {:?} failed {} times in a row, bench it for {:?}",
+                    cur.source,
+                    cur.state.consecutive_failures,
+                    bench_time
+                );
+                cur.benched_until = Some(self.clock.now() + bench_time);
+            }
+        }
+    }
+    /// 未被暂停的 Cookie 按各自的冷却时间每秒最多能绘制的次数
+    pub fn paint_rate(&self, wait_time: u64) -> f64 {
+        let list = self.list.lock().unwrap();
+        let now = self.clock.now();
+        list.iter()
+            .filter(|cur| !matches!(cur.benched_until, Some(benched_until) if benched_until > now))
+            .map(|cur| match cur.cooldown(wait_time).as_secs_f64() {
+                cooldown if cooldown > 0.0 => 1.0 / cooldown,
+                _ => f64::INFINITY,
+            })
+            .sum()
+    }
+    /// 所有 Cookie 的统计信息，包括已失效的 Cookie
+    pub fn stats(&self) -> Vec<CookieStats> {
+        let removed = self.removed.lock().unwrap();
+        let list = self.list.lock().unwrap();
+        let now = self.clock.now();
+        list.iter()
+            .map(|cur| CookieStats {
+                cookie: cur.cookie.clone(),
+                source: cur.source.clone(),
+                state: cur.state.clone(),
+                benched: cur.benched_until.and_then(|benched_until| {
+                    cooldown_remaining(benched_until, now, Duration::ZERO)
+                }),
+            })
+            .chain(removed.iter().map(|(cookie, state)| CookieStats {
+                cookie: cookie.clone(),
+                source: None,
+                state: state.clone(),
+                benched: None,
+            }))
+            .collect()
+    }
+    /// 记录 cookie 现在被使用过，冷却从请求实际发出时开始计算
+    pub fn update(&self, cookie: &str) {
+        self.update_at(cookie, self.clock.now());
+    }
+    /// 记录 cookie 在 last_update 时被使用过，例如启动前的检测
+    pub fn update_at(&self, cookie: &str, last_update: std::time::Instant) {
+        let mut list = self.list.lock().unwrap();
+        if let Some(cur) = list.iter_mut().find(|cur| cur.cookie == cookie) {
+            cur.update_at(last_update);
+        }
+    }
+    /// 恢复保存的状态：按上次成功的时间计算冷却，并移除已失效的 Cookie
+    pub fn restore_state(&self, states: BTreeMap<String, CookieState>) {
+        let mut removed = self.removed.lock().unwrap();
+        let mut list = self.list.lock().unwrap();
+        let (now, wall_now) = (self.clock.now(), SystemTime::now());
+        for (cookie, state) in states {
+            if state.disabled {
+                list.retain(|cur| cur.cookie != cookie);
+                removed.insert(cookie, state);
+                continue;
+            }
+            if let Some(cur) = list.iter_mut().find(|cur| cur.cookie == cookie) {
+                if let Some(last_update) = state
+                    .since_last_success(wall_now)
+                    .and_then(|elapsed| now.checked_sub(elapsed))
+                {
+                    cur.last_update = last_update;
+                }
+                cur.state = state;
+            }
+        }
+        self.changed.notify_all();
+    }
+    /// 所有 Cookie 当前的状态，包括已失效的 Cookie
+    pub fn state(&self) -> BTreeMap<String, CookieState> {
+        let removed = self.removed.lock().unwrap();
+        let list = self.list.lock().unwrap();
+        removed
+            .iter()
+            .map(|(cookie, state)| (cookie.clone(), state.clone()))
+            .chain(
+                list.iter()
+                    .map(|cur| (cur.cookie.clone(), cur.state.clone())),
+            )
+            .collect()
+    }
+    /// 将状态写入 path
+    pub fn save_state<T>(&self, path: &T) -> Result<(), ScriptError>
+    where
+        T: AsRef<Path>,
+    {
+        // 在持有 saving 时读取状态，较早读取的状态不会覆盖较新的状态
+        let _saving = self.saving.lock().unwrap();
+        let content = serde_json::to_string_pretty(&self.state())?;
+        write_atomic(path, content)
+    }
+    /// 用重新从目录读取的 cookies 更新列表，返回新增与移除的数量
+    /// 未变化的 Cookie 保留冷却状态，内容变化的文件沿用原 Cookie 的冷却状态
+    /// 来自 unreadable 中文件的 Cookie 保持不变
+    pub fn sync(&self, cookies: Vec<Cookie>, unreadable: &[PathBuf]) -> (usize, usize) {
+        let removed = self.removed.lock().unwrap();
         let mut list = self.list.lock().unwrap();
-        for i in 0..list.len() {
-            if list.get(i).unwrap().cookie == cookie {
-                list.remove(i);
-                break;
+        // 不是从文件读取的 Cookie 不受目录变化影响
+        let (mut old, kept): (Vec<Cookie>, Vec<Cookie>) =
+            list.drain(..).partition(|cookie| match &cookie.source {
+                Some(source) => !unreadable.contains(source),
+                None => false,
+            });
+        *list = kept;
+        let mut added = 0;
+        for mut cookie in cookies {
+            if removed.contains_key(&cookie.cookie) {
+                continue;
+            }
+            if let Some(pos) = old.iter().position(|cur| cur.cookie == cookie.cookie) {
+                // 未变化的 Cookie 保留所有状态
+                let mut prev = old.swap_remove(pos);
+                prev.source = cookie.source;
+                prev.wait_time = cookie.wait_time;
+                prev.weight = cookie.weight;
+                list.push(prev);
+                continue;
             }
+            match old.iter().position(|cur| cur.source == cookie.source) {
+                Some(pos) => cookie.last_update = old.swap_remove(pos).last_update,
+                None => added += 1,
+            }
+            list.push(cookie);
+        }
+        self.changed.notify_all();
+        (added, old.len())
+    }
+    pub fn len(&self) -> usize {
+        self.list.lock().unwrap().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.lock().unwrap().is_empty()
+    }
+    /// 唤醒所有等待 Cookie 的线程，例如在收到停止信号后
+    pub fn notify(&self) {
+        // get_cookie 持有 list 检查停止信号后才开始等待，先取得 list 才不会错过唤醒
+        let _list = self.list.lock().unwrap();
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ratelimit::MockClock;
+
+    /// 在 clock 的当前时间读取自 source 的 Cookie
+    fn cookie_at(raw_cookie: RawCookie, source: Option<&str>, clock: &MockClock) -> Cookie {
+        Cookie {
+            last_update: clock.now(),
+            source: source.map(PathBuf::from),
+            ..Cookie::new(raw_cookie)
+        }
+    }
+
+    fn cookie_list(cookies: &[&str]) -> (CookieList, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::default());
+        let list = cookies
+            .iter()
+            .map(|cookie| cookie_at(RawCookie::new(cookie.to_string()), None, &clock))
+            .collect();
+        (CookieList::with_clock(list, clock.clone()), clock)
+    }
+
+    fn config(wait_time: u64) -> Config {
+        Config {
+            wait_time,
+            ..Config::default()
         }
     }
+
+    fn parse(content: &str) -> Vec<String> {
+        parse_cookie_file(content)
+            .unwrap()
+            .into_iter()
+            .map(|raw| raw.cookie)
+            .collect()
+    }
+
+    #[test]
+    fn cookie_file_formats() {
+        let cookie = "_uid=1;__client_id=abc";
+        assert_eq!(parse(r#"{"cookie": "_uid=1;__client_id=abc"}"#), [cookie]);
+        assert_eq!(parse(r#"{"uid": 1, "client_id": "abc"}"#), [cookie]);
+        assert_eq!(
+            parse(r#"[{"uid": "2", "client_id": "def"}, "_uid=1;__client_id=abc"]"#),
+            ["_uid=2;__client_id=def", cookie]
+        );
+        assert_eq!(
+            parse("# accounts\n_uid=1;__client_id=abc\n\n_uid=2;__client_id=def\n"),
+            [cookie, "_uid=2;__client_id=def"]
+        );
+        assert_eq!(
+            parse(
+                "# Netscape HTTP Cookie File\n\
+                 .luogu.com.cn\tTRUE\t/\tFALSE\t0\t_uid\t1\n\
+                 #HttpOnly_.luogu.com.cn\tTRUE\t/\tFALSE\t0\t__client_id\tabc\n\
+                 .example.com\tTRUE\t/\tFALSE\t0\tother\tx\n"
+            ),
+            [cookie]
+        );
+    }
+
+    #[test]
+    fn invalid_cookie_file() {
+        assert!(matches!(
+            parse_cookie_file("{\"cookie\": 1}"),
+            Err(ScriptError::FailedParseJson(_))
+        ));
+        assert!(matches!(
+            parse_cookie_file("# empty\n"),
+            Err(ScriptError::InvalidCookieFile(_))
+        ));
+        assert!(matches!(
+            parse_cookie_file("luogu.com.cn\tTRUE\t/\tFALSE\t0\t_uid\t1\n"),
+            Err(ScriptError::InvalidCookieFile(_))
+        ));
+    }
+
+    #[test]
+    fn picks_earliest_ready_cookie() {
+        let (list, clock) = cookie_list(&["a", "b"]);
+        let config = config(30);
+        let shutdown = Shutdown::default();
+
+        clock.advance(std::time::Duration::from_secs(30));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "a");
+        // a 正在冷却，不会阻塞 b
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "b");
+
+        clock.advance(std::time::Duration::from_secs(30));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "a");
+    }
+
+    #[test]
+    fn empty_list() {
+        let (list, _) = cookie_list(&["a"]);
+        list.remove_cookie("a");
+        assert!(matches!(
+            list.get_cookie(&config(0), &Shutdown::default()),
+            Err(ScriptError::NoCookieAvailable)
+        ));
+    }
+
+    #[test]
+    fn sync_with_dir() {
+        let (list, clock) = cookie_list(&[]);
+        let config = config(30);
+        let shutdown = Shutdown::default();
+        let cookie = |cookie: &str, file: &str| {
+            cookie_at(RawCookie::new(cookie.to_string()), Some(file), &clock)
+        };
+        assert_eq!(
+            list.sync(vec![cookie("a", "a.json"), cookie("b", "b.json")], &[]),
+            (2, 0)
+        );
+
+        clock.advance(std::time::Duration::from_secs(30));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "a");
+        // b.json 的内容被修改为 c，沿用 b 的冷却状态；a 仍在冷却
+        assert_eq!(
+            list.sync(vec![cookie("a", "a.json"), cookie("c", "b.json")], &[]),
+            (0, 0)
+        );
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "c");
+
+        // b.json 无法解析时保留其中原有的 Cookie
+        assert_eq!(
+            list.sync(vec![cookie("a", "a.json")], &["b.json".into()]),
+            (0, 0)
+        );
+        assert_eq!(list.len(), 2);
+
+        // 失效的 Cookie 不会因为文件仍然存在而被重新加入
+        list.remove_cookie("c");
+        assert_eq!(list.sync(vec![cookie("c", "b.json")], &[]), (0, 1));
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn restore_state() {
+        let (list, clock) = cookie_list(&["a", "b", "c"]);
+        let config = config(30);
+        let shutdown = Shutdown::default();
+        let ten_seconds_ago = SystemTime::now() - Duration::from_secs(10);
+        let mut states = BTreeMap::new();
+        states.insert(
+            "b".to_string(),
+            CookieState {
+                last_success: Some(unix_millis(ten_seconds_ago)),
+                successes: 3,
+                ..CookieState::default()
+            },
+        );
+        states.insert(
+            "c".to_string(),
+            CookieState {
+                disabled: true,
+                ..CookieState::default()
+            },
+        );
+        list.restore_state(states);
+        assert_eq!(list.len(), 2);
+
+        // b 在重启前已经冷却了 10 秒，比新读取的 a 更早可用
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "b");
+
+        list.record_success("b");
+        list.record_failure("a", &config);
+        let state = list.state();
+        assert_eq!(state["a"].failures, 1);
+        assert_eq!(state["b"].successes, 4);
+        assert!(state["c"].disabled);
+    }
+
+    #[test]
+    fn cooldown_from_request() {
+        let (list, clock) = cookie_list(&["a", "b"]);
+        let config = config(30);
+        let shutdown = Shutdown::default();
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "a");
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "b");
+        // a 等待了 10 秒才真正发出请求，冷却从此时开始
+        clock.advance(Duration::from_secs(5));
+        list.update("a");
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "b");
+    }
+
+    #[test]
+    fn probe_after_restore() {
+        let (list, clock) = cookie_list(&["a", "b"]);
+        let config = config(30);
+        let shutdown = Shutdown::default();
+        let mut states = BTreeMap::new();
+        states.insert(
+            "b".to_string(),
+            CookieState {
+                last_success: Some(unix_millis(SystemTime::now() - Duration::from_secs(60))),
+                ..CookieState::default()
+            },
+        );
+        list.restore_state(states);
+        // 恢复状态后 b 刚被检测过，应从检测时开始冷却
+        clock.advance(Duration::from_secs(10));
+        list.update_at("b", clock.now());
+
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "a");
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "b");
+    }
+
+    #[test]
+    fn bench_failing_cookie() {
+        let (list, clock) = cookie_list(&["a", "b"]);
+        let mut config = config(0);
+        config.cookie_backoff = CookieBackoffConfig {
+            failures: 2,
+            initial: 60,
+            max: 100,
+        };
+        let shutdown = Shutdown::default();
+        let stats = |cookie: &str| {
+            list.stats()
+                .into_iter()
+                .find(|stats| stats.cookie == cookie)
+                .unwrap()
+        };
+
+        list.record_failure("a", &config);
+        assert_eq!(stats("a").benched, None);
+        list.record_failure("a", &config);
+        assert_eq!(stats("a").benched, Some(Duration::from_secs(60)));
+        // a 被暂停时只会取出 b
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "b");
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "b");
+
+        // 暂停时间翻倍，但不超过上限
+        list.record_failure("a", &config);
+        assert_eq!(stats("a").benched, Some(Duration::from_secs(100)));
+        assert_eq!(stats("a").state.consecutive_failures, 3);
+
+        clock.advance(Duration::from_secs(100));
+        assert_eq!(stats("a").benched, None);
+        let cookies = [
+            list.get_cookie(&config, &shutdown).unwrap().unwrap(),
+            list.get_cookie(&config, &shutdown).unwrap().unwrap(),
+        ];
+        assert!(cookies.contains(&"a".to_string()));
+        list.record_success("a");
+        assert_eq!(stats("a").state.consecutive_failures, 0);
+        assert_eq!(stats("a").state.failures, 3);
+    }
+
+    #[test]
+    fn paint_rate() {
+        let (list, _) = cookie_list(&["a", "b"]);
+        let mut config = config(30);
+        assert_eq!(list.paint_rate(30), 2.0 / 30.0);
+
+        // 单独设置的冷却时间与被暂停的 Cookie 都会影响速度
+        list.sync(
+            vec![Cookie::new(RawCookie {
+                wait_time: Some(10),
+                ..RawCookie::new("fast".to_string())
+            })],
+            &[],
+        );
+        assert_eq!(list.paint_rate(30), 2.0 / 30.0 + 0.1);
+        config.cookie_backoff.failures = 1;
+        list.record_failure("fast", &config);
+        assert_eq!(list.paint_rate(30), 2.0 / 30.0);
+        assert_eq!(list.paint_rate(0), f64::INFINITY);
+    }
+
+    #[test]
+    fn cookie_overrides() {
+        let raw: Vec<RawCookie> =
+            parse_cookie_file(r#"[{"uid": 1, "client_id": "abc", "wait_time": 60, "weight": 5}]"#)
+                .unwrap();
+        assert_eq!((raw[0].wait_time, raw[0].weight), (Some(60), Some(5)));
+
+        let (list, clock) = cookie_list(&[]);
+        let cookie = |cookie: &str, wait_time: Option<u64>, weight: u32| {
+            let raw_cookie = RawCookie {
+                cookie: cookie.to_string(),
+                wait_time,
+                weight: Some(weight),
+            };
+            cookie_at(raw_cookie, Some(&format!("{}.json", cookie)), &clock)
+        };
+        list.sync(
+            vec![
+                cookie("slow", Some(60), 1),
+                cookie("a", None, 1),
+                cookie("heavy", None, 2),
+            ],
+            &[],
+        );
+        let config = config(30);
+        let shutdown = Shutdown::default();
+
+        // 同时冷却完毕时优先使用 weight 较大的
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            list.get_cookie(&config, &shutdown).unwrap().unwrap(),
+            "heavy"
+        );
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "a");
+
+        // slow 的冷却时间为 60 秒
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            list.get_cookie(&config, &shutdown).unwrap().unwrap(),
+            "heavy"
+        );
+        assert_eq!(
+            list.get_cookie(&config, &shutdown).unwrap().unwrap(),
+            "slow"
+        );
+        assert_eq!(list.get_cookie(&config, &shutdown).unwrap().unwrap(), "a");
+    }
+
+    #[test]
+    fn stop_while_waiting() {
+        let (list, _) = cookie_list(&["a"]);
+        let shutdown = Shutdown::default();
+        shutdown.trigger(crate::daemon::DaemonExit::Stopped);
+        assert!(list.get_cookie(&config(30), &shutdown).unwrap().is_none());
+    }
 }
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 守护进程结束的原因
+pub enum DaemonExit {
+    /// 所有目标均已与画板一致
+    Completed,
+    /// 被信号或调用方停止
+    Stopped,
+    /// 所有 Cookie 都已失效
+    OutOfCookies,
+}
+
+impl DaemonExit {
+    /// 对应的进程退出码
+    pub fn code(&self) -> i32 {
+        match self {
+            DaemonExit::Completed => 0,
+            DaemonExit::Stopped => 130,
+            DaemonExit::OutOfCookies => 2,
+        }
+    }
+}
+
+/// 停止信号，所有后台线程在等待时都会检查它
+pub struct Shutdown {
+    reason: Mutex<Option<DaemonExit>>,
+    condvar: Condvar,
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown {
+            reason: Mutex::new(None),
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+impl Shutdown {
+    /// 发出停止信号，只有第一次调用的原因会被记录
+    pub fn trigger(&self, reason: DaemonExit) {
+        let mut cur = self.reason.lock().unwrap();
+        if cur.is_none() {
+            *cur = Some(reason);
+        }
+        self.condvar.notify_all();
+    }
+
+    pub fn reason(&self) -> Option<DaemonExit> {
+        *self.reason.lock().unwrap()
+    }
+    pub fn is_triggered(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    /// 等待 timeout，若期间收到停止信号则提前返回 true
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut reason = self.reason.lock().unwrap();
+        while reason.is_none() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            reason = self.condvar.wait_timeout(reason, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+/// 正在运行的守护进程
+pub struct DaemonHandle {
+    shutdown: Arc<Shutdown>,
+    thread: JoinHandle<()>,
+}
+
+impl DaemonHandle {
+    pub fn new(shutdown: Arc<Shutdown>, thread: JoinHandle<()>) -> DaemonHandle {
+        DaemonHandle { shutdown, thread }
+    }
+
+    /// 用于在信号处理等场景下停止守护进程
+    pub fn shutdown(&self) -> Arc<Shutdown> {
+        self.shutdown.clone()
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.trigger(DaemonExit::Stopped);
+    }
+
+    /// 等待所有线程退出
+    pub fn wait(self) -> DaemonExit {
+        if self.thread.join().is_err() {
+            log::error!("Daemon thread panicked");
+        }
+        self.shutdown.reason().unwrap_or(DaemonExit::Stopped)
+    }
+}
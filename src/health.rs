@@ -0,0 +1,216 @@
+use crate::cookie::Cookie;
+use crate::daemon::Shutdown;
+use crate::node::{NodeOpt, PaintError};
+use crate::paintboard::{get_board, parse_board};
+use crate::ratelimit::{SystemClock, TokenBucket};
+use crate::{Config, ScriptError};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use reqwest::blocking::Client;
+
+#[derive(Debug)]
+/// Cookie 的检测结果
+pub enum CookieHealth {
+    Valid,
+    /// 服务器拒绝了该 Cookie
+    Expired,
+    /// 格式不正确，没有发送给服务器
+    Malformed(&'static str),
+    /// 因网络等原因无法判断
+    Unknown(ScriptError),
+}
+
+impl CookieHealth {
+    /// 是否可以确定该 Cookie 无法使用
+    pub fn is_dead(&self) -> bool {
+        matches!(self, CookieHealth::Expired | CookieHealth::Malformed(_))
+    }
+}
+
+impl std::fmt::Display for CookieHealth {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CookieHealth::Valid => write!(formatter, "valid"),
+            CookieHealth::Expired => write!(formatter, "expired"),
+            CookieHealth::Malformed(reason) => write!(formatter, "malformed: {}", reason),
+            CookieHealth::Unknown(err) => write!(formatter, "unknown: {}", err),
+        }
+    }
+}
+
+/// 单个 Cookie 的检测报告
+pub struct CookieReport {
+    pub cookie: String,
+    pub source: Option<PathBuf>,
+    pub health: CookieHealth,
+    /// 发出检测请求的时间，该 Cookie 从此时开始冷却；格式不正确时没有发出请求
+    pub probed_at: Option<Instant>,
+}
+
+/// 不联网检查 Cookie 的格式
+/// 格式应为 `_uid=x;__client_id=xxxxxxxx`
+pub fn check_format(cookie: &str) -> Result<(), &'static str> {
+    if cookie.is_empty() {
+        return Err("empty cookie");
+    }
+    if cookie
+        .chars()
+        .any(|chr| chr.is_whitespace() || chr.is_control())
+    {
+        return Err("contains whitespace or control characters");
+    }
+    if !cookie.contains("_uid=") || !cookie.contains("__client_id=") {
+        return Err("missing _uid or __client_id");
+    }
+    Ok(())
+}
+
+/// 用 probe 试探 Cookie 是否有效
+/// probe 应为画板上已有的颜色，这样不会改变画板；
+/// 但 probe 的颜色是在获取画板时读取的，若之后该点被其他人修改，检测会将其改回旧的颜色。
+/// 检测请求与正常绘制一样会使该 Cookie 进入冷却
+pub fn check_cookie(
+    cookie: &str,
+    probe: &NodeOpt,
+    config: &Config,
+    client: &Client,
+) -> CookieHealth {
+    if let Err(reason) = check_format(cookie) {
+        return CookieHealth::Malformed(reason);
+    }
+    match probe.update(cookie, config, client) {
+        Ok(()) => CookieHealth::Valid,
+        // 冷却中说明服务器接受了该 Cookie
+        Err(ScriptError::PaintRejected(PaintError::CoolingDown)) => CookieHealth::Valid,
+        Err(ScriptError::CookieOutdated) => CookieHealth::Expired,
+        Err(err) => CookieHealth::Unknown(err),
+    }
+}
+
+/// 依次检测所有 Cookie，请求频率受 `[rate_limit]` 限制
+pub fn check_cookies(
+    cookies: &[Cookie],
+    config: &Config,
+    client: &Client,
+) -> Result<Vec<CookieReport>, ScriptError> {
+    let board = parse_board(&get_board(client, config)?)?;
+    let probe = NodeOpt {
+        x: 0,
+        y: 0,
+        color: *board
+            .first()
+            .and_then(|line| line.first())
+            .ok_or(ScriptError::InvalidBoardData(0, 0))?,
+    };
+    let rate_limiter = TokenBucket::new(&config.rate_limit, Arc::new(SystemClock));
+    let shutdown = Shutdown::default();
+
+    Ok(cookies
+        .iter()
+        .map(|cookie| {
+            let probed_at = if check_format(cookie.cookie()).is_ok() {
+                rate_limiter.acquire(&shutdown);
+                Some(Instant::now())
+            } else {
+                None
+            };
+            let health = check_cookie(cookie.cookie(), &probe, config, client);
+            log::info!("Cookie {:?}: {}", cookie.source(), health);
+            CookieReport {
+                cookie: cookie.cookie().to_string(),
+                source: cookie.source().map(Path::to_path_buf),
+                health,
+                probed_at,
+            }
+        })
+        .collect())
+}
+
+/// 将只包含失效 Cookie 的文件移动到 dir，返回移动的文件数
+pub fn quarantine<T>(reports: &[CookieReport], dir: &T) -> Result<usize, ScriptError>
+where
+    T: AsRef<Path>,
+{
+    let mut files: BTreeMap<&Path, bool> = BTreeMap::new();
+    for report in reports {
+        if let Some(source) = &report.source {
+            *files.entry(source).or_insert(true) &= report.health.is_dead();
+        }
+    }
+    let mut moved = 0;
+    for (file, dead) in files {
+        if !dead {
+            continue;
+        }
+        if let Some(name) = file.file_name() {
+            std::fs::create_dir_all(dir.as_ref())?;
+            std::fs::rename(file, dir.as_ref().join(name))?;
+            log::warn!("Moved {:?} to {:?}", file, dir.as_ref());
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
+
+/// 将检测结果格式化为表格，每行以换行结尾
+pub fn format_reports(reports: &[CookieReport]) -> String {
+    let name = |report: &CookieReport| {
+        report
+            .source
+            .as_ref()
+            .and_then(|source| source.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let width = reports
+        .iter()
+        .map(|report| name(report).len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let mut table = format!("{:<width$}  STATUS\n", "FILE", width = width);
+    for report in reports {
+        table += &format!(
+            "{:<width$}  {}\n",
+            name(report),
+            report.health,
+            width = width
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookie_format() {
+        assert!(check_format("_uid=1;__client_id=abcdef").is_ok());
+        assert!(check_format("").is_err());
+        assert!(check_format("_uid=1; __client_id=abcdef").is_err());
+        assert!(check_format("__client_id=abcdef").is_err());
+    }
+
+    #[test]
+    fn report_table() {
+        let report = |source: Option<&str>, health| CookieReport {
+            cookie: String::new(),
+            source: source.map(PathBuf::from),
+            health,
+            probed_at: None,
+        };
+        let reports = [
+            report(Some("cookies/account.json"), CookieHealth::Valid),
+            report(None, CookieHealth::Expired),
+        ];
+        assert_eq!(
+            format_reports(&reports),
+            "FILE          STATUS\naccount.json  valid\n-             expired\n"
+        );
+    }
+}
@@ -1,26 +1,76 @@
 use std::collections::VecDeque;
+use std::path::PathBuf;
 
 use crate::{
-    cookie::{Cookie, RawCookie},
+    cookie::{parse_cookie_file, Cookie},
     node::NodeOpt,
+    palette::Palette,
     ScriptError,
 };
 
-pub fn get_cookie_from_dir<T>(dir: &T) -> Result<VecDeque<Cookie>, ScriptError>
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+/// 绘图数据中的颜色，可以是颜色编号、颜色名称或 `#rrggbb`
+enum RawColor {
+    Index(usize),
+    Name(String),
+}
+
+impl RawColor {
+    fn resolve(&self, palette: &Palette) -> Result<usize, ScriptError> {
+        match self {
+            RawColor::Index(color) if palette.contains(*color) => Ok(*color),
+            RawColor::Index(color) => Err(ScriptError::UnknownColor(color.to_string())),
+            RawColor::Name(name) => palette
+                .find(name)
+                .ok_or_else(|| ScriptError::UnknownColor(name.clone())),
+        }
+    }
+}
+
+/// 读取 Cookie 文件夹的结果
+pub struct LoadedCookies {
+    pub cookies: VecDeque<Cookie>,
+    /// 无法读取或解析的文件
+    pub failed: Vec<(PathBuf, ScriptError)>,
+}
+
+/// 读取文件夹下的所有 Cookie 文件，单个文件解析失败不影响其他文件
+pub fn get_cookie_from_dir<T>(dir: &T) -> Result<LoadedCookies, ScriptError>
 where
     T: AsRef<std::path::Path>,
 {
-    let mut queue = VecDeque::new();
-    let cookies = std::fs::read_dir(dir.as_ref())?;
-    for cookie in cookies {
-        let content = std::fs::read_to_string(cookie?.path())?;
-        let cookie: RawCookie = serde_json::from_str(&content)?;
-        queue.push_back(Cookie::new(cookie));
+    let mut loaded = LoadedCookies {
+        cookies: VecDeque::new(),
+        failed: Vec::new(),
+    };
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir.as_ref())? {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
     }
-    Ok(queue)
+    paths.sort();
+    for path in paths {
+        let cookies = std::fs::read_to_string(&path)
+            .map_err(ScriptError::from)
+            .and_then(|content| parse_cookie_file(&content));
+        match cookies {
+            Ok(cookies) => loaded.cookies.extend(
+                cookies
+                    .into_iter()
+                    .map(|cookie| Cookie::with_source(cookie, path.clone())),
+            ),
+            Err(err) => loaded.failed.push((path, err)),
+        }
+    }
+    Ok(loaded)
 }
 
-pub fn get_node<T>(file: &T) -> Result<VecDeque<NodeOpt>, ScriptError>
+pub fn get_node<T>(file: &T, palette: &Palette) -> Result<VecDeque<NodeOpt>, ScriptError>
 where
     T: AsRef<std::path::Path>,
 {
@@ -29,16 +79,78 @@ where
 
     let mut rng = thread_rng();
     let mut queue = VecDeque::new();
-    let mut dot_draw: Vec<[usize; 3]> =
+    let mut dot_draw: Vec<(usize, usize, RawColor)> =
         serde_json::from_str(&std::fs::read_to_string(file.as_ref())?)?;
     dot_draw.shuffle(&mut rng);
 
-    for node in dot_draw {
+    for (x, y, color) in dot_draw {
         queue.push_back(NodeOpt {
-            x: node[0],
-            y: node[1],
-            color: node[2],
+            x,
+            y,
+            color: color.resolve(palette)?,
         });
     }
     Ok(queue)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::PaletteColor;
+
+    fn palette() -> Palette {
+        Palette::with_colors(vec![
+            PaletteColor {
+                rgb: [0, 0, 0],
+                name: Some("black".to_string()),
+            },
+            PaletteColor {
+                rgb: [255, 255, 255],
+                name: None,
+            },
+        ])
+    }
+
+    /// 将 content 写入临时的绘图数据文件并读取
+    fn load(name: &str, content: &str) -> Result<VecDeque<NodeOpt>, ScriptError> {
+        let file = std::env::temp_dir().join(format!(
+            "draw-script-node-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&file, content).unwrap();
+        let nodes = get_node(&file, &palette());
+        std::fs::remove_file(&file).unwrap();
+        nodes
+    }
+
+    #[test]
+    fn node_colors() {
+        let mut nodes: Vec<_> = load(
+            "colors",
+            r##"[[0, 0, 1], [1, 0, "black"], [2, 0, "#ffffff"]]"##,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|node| (node.x, node.y, node.color))
+        .collect();
+        nodes.sort();
+        assert_eq!(nodes, vec![(0, 0, 1), (1, 0, 0), (2, 0, 1)]);
+    }
+
+    #[test]
+    fn unknown_node_colors() {
+        assert!(matches!(
+            load("index", "[[0, 0, 1], [1, 0, 2]]"),
+            Err(ScriptError::UnknownColor(color)) if color == "2"
+        ));
+        assert!(matches!(
+            load("name", r#"[[0, 0, "white"]]"#),
+            Err(ScriptError::UnknownColor(color)) if color == "white"
+        ));
+        assert!(matches!(
+            load("hex", r##"[[0, 0, "#ff0000"]]"##),
+            Err(ScriptError::UnknownColor(color)) if color == "#ff0000"
+        ));
+    }
+}
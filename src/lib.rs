@@ -1,24 +1,50 @@
+pub mod client;
 pub mod config;
+pub mod convert;
 pub mod cookie;
+pub mod daemon;
+pub mod health;
 pub mod init;
 pub mod node;
 pub mod paintboard;
+pub mod palette;
+pub mod progress;
+pub mod ratelimit;
+pub mod render;
+pub mod simulator;
+pub mod snapshot;
 
 pub use self::config::*;
 
 use std::convert::TryFrom;
 
+#[derive(Debug)]
 pub enum ScriptError {
     FailedReadFile(std::io::Error),
     FailedParseToml(toml::de::Error),
+    FailedSerializeToml(toml::ser::Error),
     FailedParseJson(serde_json::Error),
     FailedParseUrl(url::ParseError),
+    FailedParseImage(image::ImageError),
     FailedProcessRequest(reqwest::Error),
+    FailedParseHeader(reqwest::header::InvalidHeaderValue),
+    InvalidHeaderName(String),
+    InvalidBoardData(usize, usize),
+    OutOfBounds(usize, usize),
     UnexpectedUrl(UrlError),
+    UnknownQuantizer(String),
+    InvalidPalette(palette::PaletteError),
+    UnknownColor(String),
+    InvalidCookieFile(&'static str),
+    InvalidSnapshot(&'static str),
+    InvalidConfig(Vec<ConfigError>),
     CookieOutdated,
+    NoCookieAvailable,
+    PaintRejected(node::PaintError),
     FailedRequest,
 }
 
+#[derive(Debug)]
 pub enum UrlError {
     InvalidHTTPUrl,
     InvalidWSUrl,
@@ -42,12 +68,30 @@ impl From<toml::de::Error> for ScriptError {
     }
 }
 
+impl From<toml::ser::Error> for ScriptError {
+    fn from(error: toml::ser::Error) -> Self {
+        ScriptError::FailedSerializeToml(error)
+    }
+}
+
 impl From<url::ParseError> for ScriptError {
     fn from(error: url::ParseError) -> Self {
         ScriptError::FailedParseUrl(error)
     }
 }
 
+impl From<image::ImageError> for ScriptError {
+    fn from(error: image::ImageError) -> Self {
+        ScriptError::FailedParseImage(error)
+    }
+}
+
+impl From<reqwest::header::InvalidHeaderValue> for ScriptError {
+    fn from(error: reqwest::header::InvalidHeaderValue) -> Self {
+        ScriptError::FailedParseHeader(error)
+    }
+}
+
 impl From<reqwest::Error> for ScriptError {
     fn from(error: reqwest::Error) -> Self {
         ScriptError::FailedProcessRequest(error)
@@ -78,11 +122,45 @@ impl std::fmt::Display for ScriptError {
         match self {
             ScriptError::FailedReadFile(err) => formatter.write_str(&format!("{}", err)),
             ScriptError::FailedParseToml(err) => formatter.write_str(&format!("{}", err)),
+            ScriptError::FailedSerializeToml(err) => formatter.write_str(&format!("{}", err)),
             ScriptError::FailedParseJson(err) => formatter.write_str(&format!("{}", err)),
             ScriptError::FailedParseUrl(err) => formatter.write_str(&format!("{}", err)),
+            ScriptError::FailedParseImage(err) => formatter.write_str(&format!("{}", err)),
             ScriptError::FailedProcessRequest(err) => formatter.write_str(&format!("{}", err)),
+            ScriptError::FailedParseHeader(err) => formatter.write_str(&format!("{}", err)),
+            ScriptError::InvalidHeaderName(name) => {
+                formatter.write_str(&format!("不合法的 Header 名称: {}", name))
+            }
+            ScriptError::InvalidBoardData(x, y) => {
+                formatter.write_str(&format!("画板数据在 ({}, {}) 处不合法", x, y))
+            }
+            ScriptError::OutOfBounds(x, y) => {
+                formatter.write_str(&format!("坐标 ({}, {}) 超出画板范围", x, y))
+            }
             ScriptError::UnexpectedUrl(err) => formatter.write_str(&format!("{}", err)),
+            ScriptError::InvalidPalette(err) => formatter.write_str(&format!("{}", err)),
+            ScriptError::UnknownColor(color) => {
+                formatter.write_str(&format!("调色板中不存在颜色: {}", color))
+            }
+            ScriptError::UnknownQuantizer(name) => {
+                formatter.write_str(&format!("未知的颜色量化方式: {}", name))
+            }
+            ScriptError::InvalidCookieFile(reason) => {
+                formatter.write_str(&format!("无法识别的 Cookie 文件: {}", reason))
+            }
+            ScriptError::InvalidSnapshot(reason) => {
+                formatter.write_str(&format!("画板快照不合法: {}", reason))
+            }
+            ScriptError::InvalidConfig(problems) => {
+                formatter.write_str(&format!("共 {} 处问题:", problems.len()))?;
+                for problem in problems {
+                    formatter.write_str(&format!("\n  - {}", problem))?;
+                }
+                Ok(())
+            }
             ScriptError::CookieOutdated => formatter.write_str("Cookie 已经过期"),
+            ScriptError::NoCookieAvailable => formatter.write_str("没有可用的 Cookie"),
+            ScriptError::PaintRejected(err) => formatter.write_str(&format!("{}", err)),
             ScriptError::FailedRequest => formatter.write_str("绘制请求失败"),
         }
     }
@@ -98,6 +176,48 @@ pub fn to_32(cur: usize) -> char {
     }
 }
 
-pub fn from_32(cur: char) -> usize {
-    cur.to_digit(33).unwrap() as usize
+pub fn from_32(cur: char) -> Option<usize> {
+    cur.to_digit(32).map(|cur| cur as usize)
+}
+
+/// 写入 path，先写入同一文件夹下的临时文件再替换，避免中断时损坏原文件
+/// 临时文件名为完整文件名加上 `.tmp`，不同的文件不会共用同一个临时文件
+pub fn write_atomic<T, C>(path: &T, contents: C) -> Result<(), ScriptError>
+where
+    T: AsRef<std::path::Path>,
+    C: AsRef<[u8]>,
+{
+    let tmp = tmp_path(path.as_ref());
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path.as_ref())?;
+    Ok(())
+}
+
+fn tmp_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tmp.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distinct_tmp_paths() {
+        assert_eq!(
+            tmp_path("state.bin".as_ref()),
+            std::path::Path::new("state.bin.tmp")
+        );
+        assert_ne!(
+            tmp_path("state.bin".as_ref()),
+            tmp_path("state.json".as_ref())
+        );
+        let path = std::env::temp_dir().join(format!("draw-script-atomic-{}", std::process::id()));
+        write_atomic(&path, "a").unwrap();
+        write_atomic(&path, "b").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "b");
+        assert!(!tmp_path(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
 }
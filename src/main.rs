@@ -1,34 +1,388 @@
-use draw_script::cookie::CookieList;
+use draw_script::client;
+use draw_script::convert::{self, Quantizer};
+use draw_script::cookie::{self, Cookie, CookieList};
+use draw_script::daemon::DaemonExit;
+use draw_script::health::{self, CookieReport};
 use draw_script::init;
-use draw_script::paintboard::{ColorArray, PaintBoard, TargetList};
-use draw_script::Config;
+use draw_script::paintboard::PaintBoard;
+use draw_script::render;
+use draw_script::simulator::{self, Simulator};
+use draw_script::snapshot::Snapshot;
+use draw_script::{Config, ConfigLayers, Override, ScriptError};
 
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Arc;
 
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(version, about = "洛谷冬日绘板脚本")]
+struct Cli {
+    /// 配置文件路径，默认为 config.toml，不存在时全部使用默认值
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// 输出合并后的配置及每一项的来源，然后退出
+    #[arg(long, global = true)]
+    print_config: bool,
+    #[command(flatten)]
+    overrides: Overrides,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// 覆盖配置文件中的对应项
+#[derive(Args)]
+struct Overrides {
+    /// 最大线程数
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+    /// 单个 Cookie 的冷却时间（秒）
+    #[arg(long, global = true)]
+    wait_time: Option<u64>,
+    /// 绘图数据文件
+    #[arg(long, global = true)]
+    node_file: Option<String>,
+    /// Cookies 文件夹
+    #[arg(long, global = true)]
+    cookie_dir: Option<String>,
+    /// 绘板地址
+    #[arg(long, global = true)]
+    board_addr: Option<String>,
+    /// WebSocket 地址
+    #[arg(long, global = true)]
+    websocket_addr: Option<String>,
+    /// 所有目标点都与绘板一致后退出
+    #[arg(long, global = true)]
+    exit_on_complete: bool,
+}
+
+impl Overrides {
+    fn to_vec(&self) -> Vec<Override> {
+        use toml::Value;
+
+        let mut overrides = Vec::new();
+        let mut push = |key: &str, value: Value| overrides.push((key.to_string(), value));
+        if let Some(threads) = self.threads {
+            push("thread_num", Value::Integer(threads as i64));
+        }
+        if let Some(wait_time) = self.wait_time {
+            push("wait_time", Value::Integer(wait_time as i64));
+        }
+        if let Some(node_file) = &self.node_file {
+            push("node_file", Value::String(node_file.clone()));
+        }
+        if let Some(cookie_dir) = &self.cookie_dir {
+            push("cookie_dir", Value::String(cookie_dir.clone()));
+        }
+        if let Some(board_addr) = &self.board_addr {
+            push("board_addr", Value::String(board_addr.clone()));
+        }
+        if let Some(websocket_addr) = &self.websocket_addr {
+            push("websocket_addr", Value::String(websocket_addr.clone()));
+        }
+        if self.exit_on_complete {
+            push("exit_on_complete", Value::Boolean(true));
+        }
+        overrides
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 开始绘制（默认）
+    Run,
+    /// 将绘图数据绘制为 PNG 图片
+    Preview { output: PathBuf },
+    /// 获取画板并绘制与目标的差异图
+    Diff { output: PathBuf },
+    /// 将图片转换为绘图数据
+    Convert {
+        /// PNG、BMP、PPM 等格式的图片
+        image: PathBuf,
+        /// 图片左上角在绘板上的 x 坐标
+        x: usize,
+        /// 图片左上角在绘板上的 y 坐标
+        y: usize,
+        /// 输出的绘图数据文件
+        output: PathBuf,
+        /// nearest、ciede2000、floyd-steinberg 或 bayer
+        #[arg(short, long, default_value = "nearest", value_parser = parse_quantizer)]
+        quantizer: Quantizer,
+    },
+    /// 检测所有 Cookies，存在失效的 Cookies 时退出码为 1
+    CheckCookies,
+    /// 获取画板并保存为快照，输出文件以 .png 结尾时保存为图片
+    FetchBoard { output: PathBuf },
+    /// 在配置中的地址上启动本地模拟服务器
+    Simulate,
+}
+
+fn parse_quantizer(name: &str) -> Result<Quantizer, String> {
+    name.parse().map_err(|err: ScriptError| err.to_string())
+}
+
 fn main() {
     pretty_env_logger::init();
-    let config = Arc::new(
-        Config::new("config.toml".to_string()).unwrap_or_else(|err| {
-            eprintln!("Error parsing the config file: {}", err);
+    let cli = Cli::parse();
+    let config = load_config(&cli);
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(config),
+        Command::Preview { output } => preview(config, &output, false),
+        Command::Diff { output } => preview(config, &output, true),
+        Command::Convert {
+            image,
+            x,
+            y,
+            output,
+            quantizer,
+        } => convert_image(&config, &image, x, y, &output, quantizer),
+        Command::CheckCookies => check_cookies(&config),
+        Command::FetchBoard { output } => fetch_board(config, &output),
+        Command::Simulate => simulate(&config),
+    }
+}
+
+/// 依次合并默认值、配置文件、环境变量与命令行参数
+fn load_config(cli: &Cli) -> Config {
+    let default_path = Path::new("config.toml");
+    let path = match &cli.config {
+        Some(path) => Some(path.as_path()),
+        None if default_path.exists() => Some(default_path),
+        None => None,
+    };
+    let mut layers = ConfigLayers::new();
+    if let Some(path) = path {
+        layers = layers.file(path).unwrap_or_else(|err| {
+            eprintln!("Error parsing the config file {}: {}", path.display(), err);
             process::exit(1);
-        }),
-    );
-    let cookie_list = CookieList::new(
-        init::get_cookie_from_dir(&config.cookie_dir).unwrap_or_else(|err| {
-            eprintln!("Error getting cookies: {}", err);
+        });
+    }
+    let layers = layers.env(std::env::vars()).cli(&cli.overrides.to_vec());
+    let config = layers.build().unwrap_or_else(|err| {
+        eprintln!("Invalid config: {}", err);
+        process::exit(1);
+    });
+    if cli.print_config {
+        match layers.dump(&config) {
+            Ok(dump) => print!("{}", dump),
+            Err(err) => {
+                eprintln!("Error printing the config: {}", err);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+    config
+}
+
+/// 读取绘图数据并创建画板
+fn load_board(config: &Arc<Config>) -> PaintBoard {
+    let nodes = init::get_node(&config.node_file, &config.palette).unwrap_or_else(|err| {
+        eprintln!("Error getting nodes: {}", err);
+        process::exit(1);
+    });
+    PaintBoard::new(Arc::clone(config), nodes).unwrap_or_else(|err| {
+        eprintln!("Error creating the paint board: {}", err);
+        process::exit(1);
+    })
+}
+
+fn load_cookies(config: &Config) -> VecDeque<Cookie> {
+    let loaded = init::get_cookie_from_dir(&config.cookie_dir).unwrap_or_else(|err| {
+        eprintln!("Error getting cookies: {}", err);
+        process::exit(1);
+    });
+    for (path, err) in &loaded.failed {
+        eprintln!("Skipped cookie file {}: {}", path.display(), err);
+    }
+    loaded.cookies
+}
+
+/// 检测所有 Cookie，并按配置隔离失效的 Cookie 文件
+fn probe_cookies(config: &Config, cookies: &VecDeque<Cookie>) -> Vec<CookieReport> {
+    let client = client::build_client(config).unwrap_or_else(|err| {
+        eprintln!("Error creating the HTTP client: {}", err);
+        process::exit(1);
+    });
+    let cookies: Vec<Cookie> = cookies.iter().cloned().collect();
+    let reports = health::check_cookies(&cookies, config, &client).unwrap_or_else(|err| {
+        eprintln!("Error checking cookies: {}", err);
+        process::exit(1);
+    });
+    if let Some(dir) = &config.quarantine_dir {
+        match health::quarantine(&reports, dir) {
+            Ok(moved) => eprintln!("{} cookie files moved to {}", moved, dir),
+            Err(err) => eprintln!("Error moving cookie files: {}", err),
+        }
+    }
+    reports
+}
+
+fn run(config: Config) {
+    config.validate().unwrap_or_else(|err| {
+        eprintln!("Invalid config: {}", err);
+        process::exit(1);
+    });
+    let config = Arc::new(config);
+    let cookies = load_cookies(&config);
+    let reports = if config.check_cookies {
+        probe_cookies(&config, &cookies)
+    } else {
+        Vec::new()
+    };
+    let cookie_list = CookieList::new(cookies);
+    if let Some(path) = &config.cookie_state_file {
+        match cookie::load_state(path) {
+            Ok(states) => cookie_list.restore_state(states),
+            Err(err) => {
+                eprintln!("Error reading the cookie state file: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+    if config.check_cookies {
+        // 检测时已经用每个 Cookie 绘制过一次，从检测时开始冷却
+        // 须在恢复状态之后设置，否则会被较早的 last_success 覆盖
+        for report in &reports {
+            if let Some(probed_at) = report.probed_at {
+                cookie_list.update_at(&report.cookie, probed_at);
+            }
+        }
+        for report in reports.iter().filter(|report| report.health.is_dead()) {
+            cookie_list.remove_cookie(&report.cookie);
+        }
+        eprintln!("{} valid cookies", cookie_list.len());
+    }
+    let paint_board = load_board(&config);
+    let handle = paint_board.start_daemon(Arc::from(cookie_list), Arc::clone(&config));
+    let shutdown = handle.shutdown();
+    ctrlc::set_handler(move || {
+        eprintln!("Stopping...");
+        shutdown.trigger(DaemonExit::Stopped);
+    })
+    .unwrap_or_else(|err| {
+        eprintln!("Error setting the signal handler: {}", err);
+        process::exit(1);
+    });
+    process::exit(handle.wait().code());
+}
+
+fn convert_image(
+    config: &Config,
+    image: &Path,
+    x: usize,
+    y: usize,
+    output: &Path,
+    quantizer: Quantizer,
+) {
+    let nodes = convert::get_node_from_image(&image, x, y, &config.palette, quantizer)
+        .unwrap_or_else(|err| {
+            eprintln!("Error converting the image: {}", err);
             process::exit(1);
-        }),
-    );
-    let paint_board = PaintBoard {
-        color: ColorArray::new(Arc::clone(&config)),
-        targets: TargetList::new(
-            Arc::clone(&config),
-            init::get_node(&config.node_file).unwrap_or_else(|err| {
-                eprintln!("Error getting nodes: {}", err);
+        });
+    convert::save_node(&output, &nodes).unwrap_or_else(|err| {
+        eprintln!("Error writing nodes: {}", err);
+        process::exit(1);
+    });
+    eprintln!("{} nodes written to {}", nodes.len(), output.display());
+}
+
+/// 绘制目标图案，或当前画板与目标的差异
+fn preview(config: Config, output: &Path, diff: bool) {
+    let config = Arc::new(config);
+    let paint_board = load_board(&config);
+    let image = if diff {
+        paint_board.refresh_board(&config).unwrap_or_else(|err| {
+            eprintln!("Error getting the board: {}", err);
+            process::exit(1);
+        });
+        render::render_diff(
+            &paint_board.color.to_vec(),
+            &paint_board.targets.to_vec(),
+            &config.palette,
+        )
+    } else {
+        render::render_targets(&paint_board.targets, &config.palette)
+    };
+    save_image(&image, output);
+}
+
+fn save_image(image: &image::RgbaImage, output: &Path) {
+    render::save_png(image, &output).unwrap_or_else(|err| {
+        eprintln!("Error writing the image: {}", err);
+        process::exit(1);
+    });
+    eprintln!("Image written to {}", output.display());
+}
+
+/// 获取画板，保存为快照或 PNG 图片
+fn fetch_board(config: Config, output: &Path) {
+    let config = Arc::new(config);
+    let paint_board = PaintBoard::new(Arc::clone(&config), VecDeque::new()).unwrap_or_else(|err| {
+        eprintln!("Error creating the paint board: {}", err);
+        process::exit(1);
+    });
+    paint_board.refresh_board(&config).unwrap_or_else(|err| {
+        eprintln!("Error getting the board: {}", err);
+        process::exit(1);
+    });
+    let is_png = output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    if is_png {
+        save_image(
+            &render::render_board(&paint_board.color, &config.palette),
+            output,
+        );
+    } else {
+        Snapshot::from_array(&paint_board.color, &config.palette)
+            .save(&output)
+            .unwrap_or_else(|err| {
+                eprintln!("Error writing the snapshot: {}", err);
                 process::exit(1);
-            }),
-        ),
+            });
+        eprintln!("Snapshot written to {}", output.display());
+    }
+}
+
+/// 在配置中的地址上启动本地模拟服务器
+fn simulate(config: &Config) {
+    let listen_addr = |url: &str| {
+        simulator::listen_addr(url).unwrap_or_else(|err| {
+            eprintln!("Invalid address {}: {}", url, err);
+            process::exit(1);
+        })
     };
-    paint_board.start_daemon(Arc::from(cookie_list), Arc::clone(&config));
+    let handle = Simulator::new(
+        config.board_width,
+        config.board_height,
+        config.palette.len(),
+        std::time::Duration::from_secs(config.wait_time),
+    )
+    .start(
+        listen_addr(&config.board_addr),
+        listen_addr(&config.websocket_addr),
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("Error starting the simulator: {}", err);
+        process::exit(1);
+    });
+    eprintln!(
+        "Simulator listening on {} and {}",
+        handle.board_addr, handle.websocket_addr
+    );
+    loop {
+        std::thread::park();
+    }
+}
+
+/// 检测所有 Cookie 并输出结果，存在失效的 Cookie 时退出码为 1
+fn check_cookies(config: &Config) {
+    let reports = probe_cookies(config, &load_cookies(config));
+    print!("{}", health::format_reports(&reports));
+    if reports.iter().any(|report| report.health.is_dead()) {
+        process::exit(1);
+    }
 }
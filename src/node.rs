@@ -2,8 +2,7 @@ use super::{Config, ScriptError};
 
 use serde::{Deserialize, Serialize};
 
-use reqwest::header;
-use reqwest::header::HeaderMap;
+use reqwest::blocking::Client;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// 单个点的信息
@@ -14,37 +13,157 @@ pub struct NodeOpt {
     pub color: usize,
 }
 
-// #[derive(Deserialize)]
-// /// Luogu 返回的状态
-// pub struct Status {
-//     status: u32,
-// }
+#[derive(Deserialize)]
+/// Luogu 返回的状态
+pub struct Status {
+    status: u32,
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+#[derive(Debug)]
+/// 绘制请求被服务器拒绝的原因
+pub enum PaintError {
+    CoolingDown,
+    OutOfBounds,
+    InvalidColor,
+    RateLimited,
+    ServerError(u32, String),
+}
+
+impl std::fmt::Display for PaintError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PaintError::CoolingDown => write!(formatter, "Cookie 冷却时间未到"),
+            PaintError::OutOfBounds => write!(formatter, "坐标超出绘板范围"),
+            PaintError::InvalidColor => write!(formatter, "颜色不合法"),
+            PaintError::RateLimited => write!(formatter, "请求过于频繁"),
+            PaintError::ServerError(status, message) => {
+                write!(formatter, "服务器错误 {}: {}", status, message)
+            }
+        }
+    }
+}
+
+/// 根据 HTTP 状态码与返回内容判断绘制结果
+/// 只有 Luogu 返回的 JSON 才能说明 Cookie 失效，
+/// 代理或 CDN 返回的 401/403 页面只按服务器错误处理
+pub fn parse_response(http_status: u16, content: &str) -> Result<(), ScriptError> {
+    if content.trim() == "[]" {
+        return Ok(());
+    }
+    let (status, message) = match serde_json::from_str::<Status>(content) {
+        Ok(Status { status, data }) => match data {
+            serde_json::Value::String(message) => (status, message),
+            data => (status, data.to_string()),
+        },
+        Err(_) => match http_status {
+            200..=299 => return Err(ScriptError::FailedRequest),
+            401 | 403 => {
+                return Err(ScriptError::PaintRejected(PaintError::ServerError(
+                    u32::from(http_status),
+                    content.to_string(),
+                )))
+            }
+            _ => (u32::from(http_status), content.to_string()),
+        },
+    };
+    let contains = |keywords: &[&str]| {
+        let message = message.to_lowercase();
+        keywords.iter().any(|keyword| message.contains(keyword))
+    };
+
+    match status {
+        200 => Ok(()),
+        401 | 403 => Err(ScriptError::CookieOutdated),
+        429 => Err(ScriptError::PaintRejected(PaintError::RateLimited)),
+        400 if contains(&["颜色", "color"]) => {
+            Err(ScriptError::PaintRejected(PaintError::InvalidColor))
+        }
+        400 if contains(&["坐标", "coordinate", "range"]) => {
+            Err(ScriptError::PaintRejected(PaintError::OutOfBounds))
+        }
+        500 if contains(&["冷却", "频繁", "cool"]) => {
+            Err(ScriptError::PaintRejected(PaintError::CoolingDown))
+        }
+        500..=599 => Err(ScriptError::PaintRejected(PaintError::ServerError(
+            status, message,
+        ))),
+        _ => Err(ScriptError::FailedRequest),
+    }
+}
 
 impl NodeOpt {
-    pub fn update(&self, cookies: &str, config: &Config) -> Result<(), ScriptError> {
-        let mut headers = HeaderMap::new();
-        headers.insert(header::REFERER, config.board_addr.parse().unwrap());
-        // headers.insert(header::COOKIE, cookies.parse().unwrap());
-        let client = reqwest::blocking::Client::new();
+    pub fn update(
+        &self,
+        cookies: &str,
+        config: &Config,
+        client: &Client,
+    ) -> Result<(), ScriptError> {
         let cookies = cookies.replace(":", "%3A");
         let mut params = std::collections::HashMap::new();
         params.insert("x", self.x.to_string());
         params.insert("y", self.y.to_string());
         params.insert("color", self.color.to_string());
         let rep = client
-            .post(&format!("{}/paint?token={}", config.board_addr, cookies))
-            .headers(headers)
+            .post(format!("{}/paint?token={}", config.board_addr, cookies))
             .form(&params)
-            .send()
-            .unwrap();
+            .send()?;
+        let http_status = rep.status().as_u16();
         let rep_content = rep.text()?;
         log::debug!("{:?} send to server, get {}", params, rep_content);
 
-        if rep_content == "[]" {
-            return Ok(());
+        let result = parse_response(http_status, &rep_content);
+        if result.is_err() {
+            log::warn!("Request failed {}", rep_content);
         }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        log::warn!("Request failed {}", rep_content);
-        Err(ScriptError::FailedRequest)
+    #[test]
+    fn classify_response() {
+        assert!(parse_response(200, "[]").is_ok());
+        assert!(parse_response(200, r#"{"status":200,"data":null}"#).is_ok());
+        assert!(matches!(
+            parse_response(200, r#"{"status":403,"data":"Token 无效"}"#),
+            Err(ScriptError::CookieOutdated)
+        ));
+        assert!(matches!(
+            parse_response(200, r#"{"status":500,"data":"操作过于频繁"}"#),
+            Err(ScriptError::PaintRejected(PaintError::CoolingDown))
+        ));
+        assert!(matches!(
+            parse_response(200, r#"{"status":400,"data":"坐标超出范围"}"#),
+            Err(ScriptError::PaintRejected(PaintError::OutOfBounds))
+        ));
+        assert!(matches!(
+            parse_response(200, r#"{"status":400,"data":"颜色不合法"}"#),
+            Err(ScriptError::PaintRejected(PaintError::InvalidColor))
+        ));
+        assert!(matches!(
+            parse_response(429, "Too Many Requests"),
+            Err(ScriptError::PaintRejected(PaintError::RateLimited))
+        ));
+        assert!(matches!(
+            parse_response(502, "<html>Bad Gateway</html>"),
+            Err(ScriptError::PaintRejected(PaintError::ServerError(502, _)))
+        ));
+        assert!(matches!(
+            parse_response(200, "<html>验证码</html>"),
+            Err(ScriptError::FailedRequest)
+        ));
+        assert!(matches!(
+            parse_response(403, "<html>Forbidden</html>"),
+            Err(ScriptError::PaintRejected(PaintError::ServerError(403, _)))
+        ));
+        assert!(matches!(
+            parse_response(401, "Unauthorized"),
+            Err(ScriptError::PaintRejected(PaintError::ServerError(401, _)))
+        ));
     }
 }
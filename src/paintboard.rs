@@ -1,66 +1,118 @@
 use crate::from_32;
 
 use crate::cookie::CookieList;
-use crate::node::NodeOpt;
-use crate::Config;
+use crate::daemon::{DaemonExit, DaemonHandle, Shutdown};
+use crate::init;
+use crate::node::{NodeOpt, PaintError};
+use crate::progress::{ProgressStats, Throughput};
+use crate::ratelimit::{SystemClock, TokenBucket};
+use crate::snapshot::Snapshot;
+use crate::{Config, ScriptError};
 
 use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
 
-use reqwest::header;
-use reqwest::header::HeaderMap;
+use reqwest::blocking::Client;
 
 pub struct TargetList {
     targets: Mutex<VecDeque<NodeOpt>>,
+    positions: Mutex<Vec<(usize, usize)>>,
     array: ColorArray,
 }
 
 impl TargetList {
-    pub fn new(config: Arc<Config>, list: VecDeque<NodeOpt>) -> TargetList {
+    /// list 中有超出画板范围的点时返回 OutOfBounds
+    pub fn new(config: Arc<Config>, list: VecDeque<NodeOpt>) -> Result<TargetList, ScriptError> {
         let array = ColorArray::new(config.clone());
-        for i in 0..config.board_width {
-            for j in 0..config.board_height {
-                array.set_color(i, j, None);
-            }
-        }
-        for node in &list {
-            array.set_color(node.x, node.y, Some(node.color));
-        }
-        TargetList {
+        array.replace(TargetList::target_colors(&config, &list)?)?;
+        Ok(TargetList {
+            positions: Mutex::new(list.iter().map(|node| (node.x, node.y)).collect()),
             targets: Mutex::new(list),
             array,
+        })
+    }
+
+    fn target_colors(
+        config: &Config,
+        list: &VecDeque<NodeOpt>,
+    ) -> Result<Vec<Vec<Option<usize>>>, ScriptError> {
+        let mut colors = vec![vec![None; config.board_height]; config.board_width];
+        for node in list {
+            match colors.get_mut(node.x).and_then(|line| line.get_mut(node.y)) {
+                Some(color) => *color = Some(node.color),
+                None => return Err(ScriptError::OutOfBounds(node.x, node.y)),
+            }
         }
+        Ok(colors)
     }
 
-    pub fn get_target(&self, paint_board: &PaintBoard) -> NodeOpt {
-        loop {
+    /// 用新的目标替换所有目标，只有与 paint_board 不一致的点会加入队列，
+    /// list 中有超出画板范围的点时保留原有目标
+    pub fn replace(
+        &self,
+        config: &Config,
+        list: VecDeque<NodeOpt>,
+        paint_board: &PaintBoard,
+    ) -> Result<(), ScriptError> {
+        let colors = TargetList::target_colors(config, &list)?;
+        // 替换期间持有队列的锁，get_target 不会看到新旧目标混杂的状态
+        let mut targets = self.targets.lock().unwrap();
+        self.array.replace(colors)?;
+        *self.positions.lock().unwrap() = list.iter().map(|node| (node.x, node.y)).collect();
+        *targets = list
+            .into_iter()
+            .filter(|node| !paint_board.check(node.x, node.y))
+            .collect();
+        Ok(())
+    }
+
+    /// 取出下一个需要绘制的点，收到停止信号时返回 None
+    pub fn get_target(&self, paint_board: &PaintBoard, shutdown: &Shutdown) -> Option<NodeOpt> {
+        while !shutdown.is_triggered() {
             // 避免 targets 堵塞，只在查找时 lock
             {
                 let mut targets = self.targets.lock().unwrap();
-                while targets.len() > 0
+                while !targets.is_empty()
                     && paint_board.check(targets.front().unwrap().x, targets.front().unwrap().y)
                 {
                     targets.pop_front();
                 }
-                if targets.len() > 0 {
+                if !targets.is_empty() {
                     let node = targets.front().unwrap().clone();
                     targets.pop_front();
-                    return node;
+                    return Some(node);
                 }
             }
 
             log::info!("There is nothing to do.");
-            std::thread::sleep(std::time::Duration::from_secs(1));
+            shutdown.wait_timeout(std::time::Duration::from_secs(1));
         }
+        None
+    }
+
+    /// 所有目标点的坐标
+    pub fn positions(&self) -> Vec<(usize, usize)> {
+        self.positions.lock().unwrap().clone()
     }
 
     pub fn queue_empty(&self) -> bool {
-        self.targets.lock().unwrap().len() == 0
+        self.targets.lock().unwrap().is_empty()
     }
 
     pub fn color(&self, x: usize, y: usize) -> Option<usize> {
         self.array.color(x, y)
     }
+    /// 复制所有目标颜色，不是目标的点为 None
+    pub fn to_vec(&self) -> Vec<Vec<Option<usize>>> {
+        self.array.to_vec()
+    }
+    /// 放弃绘制某个点，之后不会再将其加入队列
+    pub fn remove_target(&self, x: usize, y: usize) {
+        // 画板范围外的点本来就不是目标
+        self.array.set_color(x, y, None).ok();
+    }
     pub fn add_list(&self, x: usize, y: usize) {
         let mut targets = self.targets.lock().unwrap();
         match self.array.color(x, y) {
@@ -74,25 +126,139 @@ impl TargetList {
     }
 }
 
+/// 画板上所有点的颜色
+///
+/// 每个点是一个单独的原子变量，读写单个点不需要加锁，
+/// 刷新画板时用 `replace_all` 批量写入
 pub struct ColorArray {
-    array: Mutex<Vec<Vec<Option<usize>>>>,
+    /// 第 x * height + y 个为 (x, y) 的颜色，`UNKNOWN` 表示未知
+    cells: Vec<AtomicU8>,
+    width: usize,
+    height: usize,
+}
+
+/// 未知颜色在 ColorArray 中的值
+const UNKNOWN: u8 = u8::MAX;
+
+fn encode_color(color: Option<usize>) -> Result<u8, ScriptError> {
+    match color {
+        None => Ok(UNKNOWN),
+        Some(color) => u8::try_from(color)
+            .ok()
+            .filter(|cell| *cell != UNKNOWN)
+            .ok_or_else(|| ScriptError::UnknownColor(color.to_string())),
+    }
+}
+
+fn decode_color(cell: u8) -> Option<usize> {
+    if cell == UNKNOWN {
+        None
+    } else {
+        Some(usize::from(cell))
+    }
 }
 
 impl ColorArray {
     pub fn new(config: Arc<Config>) -> ColorArray {
         ColorArray {
-            array: Mutex::from(vec![
-                vec![Some(1); config.board_height + 10];
-                config.board_width + 10
-            ]),
+            cells: (0..config.board_width * config.board_height)
+                .map(|_| AtomicU8::new(1))
+                .collect(),
+            width: config.board_width,
+            height: config.board_height,
         }
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+    fn cell(&self, x: usize, y: usize) -> Option<&AtomicU8> {
+        if self.contains(x, y) {
+            Some(&self.cells[x * self.height + y])
+        } else {
+            None
+        }
+    }
+    /// 检查第 x 行有 lines[x] 个点的数据是否都在画板范围内，
+    /// 否则返回第一个超出范围的坐标
+    pub fn check_size<I>(&self, lines: I) -> Result<(), ScriptError>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        for (x, len) in lines.into_iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            if x >= self.width {
+                return Err(ScriptError::OutOfBounds(x, 0));
+            }
+            if len > self.height {
+                return Err(ScriptError::OutOfBounds(x, self.height));
+            }
+        }
+        Ok(())
+    }
+    /// 复制所有颜色，第 x 行第 y 个为 (x, y) 的颜色
+    pub fn to_vec(&self) -> Vec<Vec<Option<usize>>> {
+        if self.height == 0 {
+            return vec![Vec::new(); self.width];
+        }
+        self.cells
+            .chunks(self.height)
+            .map(|line| {
+                line.iter()
+                    .map(|cell| decode_color(cell.load(Ordering::Relaxed)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// 画板范围外的点为 None
     pub fn color(&self, x: usize, y: usize) -> Option<usize> {
-        self.array.lock().unwrap()[x][y]
+        self.cell(x, y)
+            .and_then(|cell| decode_color(cell.load(Ordering::Relaxed)))
+    }
+    pub fn set_color(&self, x: usize, y: usize, color: Option<usize>) -> Result<(), ScriptError> {
+        let cell = self.cell(x, y).ok_or(ScriptError::OutOfBounds(x, y))?;
+        cell.store(encode_color(color)?, Ordering::Relaxed);
+        Ok(())
+    }
+    /// 一次性替换所有颜色，array 中没有的点为 None
+    pub fn replace(&self, array: Vec<Vec<Option<usize>>>) -> Result<(), ScriptError> {
+        self.check_size(array.iter().map(Vec::len))?;
+        let mut colors = vec![vec![None; self.height]; self.width];
+        for (line, new_line) in colors.iter_mut().zip(array) {
+            line[..new_line.len()].copy_from_slice(&new_line);
+        }
+        self.replace_all(&colors)
     }
-    pub fn set_color(&self, x: usize, y: usize, color: Option<usize>) {
-        self.array.lock().unwrap()[x][y] = color
+    /// 批量写入颜色，第 x 行第 y 个为 (x, y) 的新颜色，lines 中没有的点保持不变；
+    /// 有点超出画板范围或颜色无法保存时不写入任何点
+    pub fn replace_all<L, C>(&self, lines: &[L]) -> Result<(), ScriptError>
+    where
+        L: AsRef<[C]>,
+        C: Copy + Into<Option<usize>>,
+    {
+        self.check_size(lines.iter().map(|line| line.as_ref().len()))?;
+        for line in lines {
+            for color in line.as_ref() {
+                encode_color((*color).into())?;
+            }
+        }
+        for (x, line) in lines.iter().enumerate() {
+            let start = x * self.height;
+            let cells = &self.cells[start..start + line.as_ref().len()];
+            for (cell, color) in cells.iter().zip(line.as_ref()) {
+                cell.store(encode_color((*color).into())?, Ordering::Relaxed);
+            }
+        }
+        Ok(())
     }
 }
 
@@ -100,33 +266,82 @@ impl ColorArray {
 pub struct PaintBoard {
     pub color: ColorArray,
     pub targets: TargetList,
+    /// 所有绘板请求共用的 HTTP 客户端
+    pub client: Client,
+    /// 最近一分钟的绘制结果
+    pub throughput: Throughput,
 }
 
 /// 获取画板状态
-pub fn get_board(config: &Config) -> Option<String> {
-    let mut headers = HeaderMap::new();
-    headers.insert(header::REFERER, config.board_addr.parse().unwrap());
-    let client = reqwest::blocking::Client::new();
+pub fn get_board(client: &Client, config: &Config) -> Result<String, ScriptError> {
+    let request = || -> Result<String, ScriptError> {
+        let rep = client.get(format!("{}/board", config.board_addr)).send()?;
+        Ok(rep.error_for_status()?.text()?)
+    };
     // try 3 times to send request
-    for i in 0..3 {
-        let rep = client.get(&format!("{}/board", config.board_addr)).send();
-        match rep {
-            Ok(res) => {
-                return Some(res.text().unwrap());
-            }
-            Err(_err) => {
-                log::warn!("Get board failed! {} retries remaining.", 3 - i);
+    let mut result = request();
+    for i in 1..3 {
+        match &result {
+            Ok(_) => break,
+            Err(err) => {
+                log::warn!("Get board failed: {}! {} retries remaining.", err, 3 - i);
             }
         }
+        result = request();
     }
-    log::error!("All retries to get board failed!");
-    None
+    result
+}
+
+/// 解析画板，第 x 行第 y 个字符为 (x, y) 的颜色
+pub fn parse_board(raw_board: &str) -> Result<Vec<Vec<usize>>, ScriptError> {
+    raw_board
+        .lines()
+        .enumerate()
+        .map(|(x, line)| {
+            line.chars()
+                .enumerate()
+                .map(|(y, chr)| from_32(chr).ok_or(ScriptError::InvalidBoardData(x, y)))
+                .collect()
+        })
+        .collect()
 }
 
 impl PaintBoard {
-    pub fn get_update(&self) -> NodeOpt {
+    pub fn new(config: Arc<Config>, list: VecDeque<NodeOpt>) -> Result<PaintBoard, ScriptError> {
+        Ok(PaintBoard {
+            color: ColorArray::new(config.clone()),
+            targets: TargetList::new(config.clone(), list)?,
+            client: crate::client::build_client(&config)?,
+            throughput: Throughput::default(),
+        })
+    }
+
+    pub fn get_update(&self, shutdown: &Shutdown) -> Option<NodeOpt> {
         log::debug!("Start to get work{:?}", std::time::Instant::now());
-        self.targets.get_target(self)
+        self.targets.get_target(self, shutdown)
+    }
+    /// 当前的绘制进度，cookie_rate 为可用的 Cookie 每秒最多能绘制的次数
+    pub fn stats(&self, cookie_rate: f64, config: &Config) -> ProgressStats {
+        let (mut correct, mut wrong) = (0, 0);
+        for (x, y) in self.targets.positions() {
+            // 跳过已放弃的点
+            if self.targets.color(x, y).is_none() {
+                continue;
+            }
+            if self.check(x, y) {
+                correct += 1;
+            } else {
+                wrong += 1;
+            }
+        }
+        ProgressStats::new(correct, wrong, &self.throughput, cookie_rate, config)
+    }
+    /// 所有目标点是否都已与画板一致
+    pub fn is_complete(&self) -> bool {
+        self.targets
+            .positions()
+            .into_iter()
+            .all(|(x, y)| self.check(x, y))
     }
     pub fn check(&self, x: usize, y: usize) -> bool {
         match self.targets.color(x, y) {
@@ -137,74 +352,255 @@ impl PaintBoard {
             None => true,
         }
     }
-    pub fn set_color(&self, x: usize, y: usize, color: Option<usize>) {
-        self.color.set_color(x, y, color);
+    pub fn set_color(&self, x: usize, y: usize, color: Option<usize>) -> Result<(), ScriptError> {
+        self.color.set_color(x, y, color)?;
         if !self.check(x, y) {
             self.targets.add_list(x, y);
         }
+        Ok(())
     }
-    fn refresh_board(&self, config: &Config) {
-        let raw_board = get_board(config);
-        match raw_board {
-            None => {
-                log::error!("Failed to refresh board!");
-            } // just log and skip if the process failed to get board from remote server
-            Some(raw_board) => {
-                for (i, line) in raw_board.lines().enumerate() {
-                    for (j, chr) in line.chars().enumerate() {
-                        self.set_color(i, j, Some(from_32(chr)));
-                    }
+    /// 将目标点标记为未知，使其重新加入队列
+    fn forget(&self, opt: &NodeOpt) {
+        // 目标点在创建时已经检查过范围
+        if let Err(err) = self.set_color(opt.x, opt.y, None) {
+            log::error!("Failed to reset target: {}", err);
+        }
+    }
+    /// 替换绘制目标，用于绘图数据被修改后
+    pub fn replace_targets(
+        &self,
+        config: &Config,
+        list: VecDeque<NodeOpt>,
+    ) -> Result<(), ScriptError> {
+        self.targets.replace(config, list, self)
+    }
+    /// 将快照中的颜色写入画板，例如在第一次获取画板前使用本地缓存，
+    /// 快照超出画板范围时不写入任何颜色
+    pub fn apply_snapshot(&self, snapshot: &Snapshot) -> Result<(), ScriptError> {
+        self.color
+            .check_size(snapshot.colors.iter().map(Vec::len))?;
+        for (x, line) in snapshot.colors.iter().enumerate() {
+            for (y, color) in line.iter().enumerate() {
+                if color.is_some() {
+                    self.set_color(x, y, *color)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 读取 board_cache 作为初始画板
+    fn load_cache(&self, config: &Config) {
+        let path = match &config.board_cache {
+            Some(path) => path,
+            None => return,
+        };
+        match Snapshot::load(path) {
+            Ok(snapshot)
+                if snapshot.matches(config.board_width, config.board_height, &config.palette) =>
+            {
+                match self.apply_snapshot(&snapshot) {
+                    Ok(()) => log::info!("Loaded board cache from {}", path),
+                    Err(err) => log::warn!("Failed to apply board cache {}: {}", path, err),
                 }
             }
+            Ok(_) => log::warn!("Board cache {} does not match the config, ignored", path),
+            Err(ScriptError::FailedReadFile(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            }
+            Err(err) => log::warn!("Failed to load board cache {}: {}", path, err),
+        }
+    }
+    /// 将当前画板写入 board_cache
+    fn save_cache(&self, config: &Config) {
+        if let Some(path) = &config.board_cache {
+            if let Err(err) = Snapshot::from_array(&self.color, &config.palette).save(path) {
+                log::error!("Failed to save board cache: {}", err);
+            }
+        }
+    }
+    /// 获取并写入画板，服务器返回的画板超出范围时不写入任何颜色
+    pub fn refresh_board(&self, config: &Config) -> Result<(), ScriptError> {
+        let board = parse_board(&get_board(&self.client, config)?)?;
+        self.color.check_size(board.iter().map(Vec::len))?;
+        self.color.replace_all(&board)?;
+        self.requeue();
+        Ok(())
+    }
+    /// 将所有与画板不一致的目标点重新加入队列
+    fn requeue(&self) {
+        for (x, y) in self.targets.positions() {
+            if !self.check(x, y) {
+                self.targets.add_list(x, y);
+            }
         }
     }
 
-    pub fn start_daemon(self, cookie_list: Arc<CookieList>, config: Arc<Config>) {
+    pub fn start_daemon(self, cookie_list: Arc<CookieList>, config: Arc<Config>) -> DaemonHandle {
         let board = Arc::from(self);
-        PaintBoard::start_daemon_arc(board, cookie_list, config);
+        PaintBoard::start_daemon_arc(board, cookie_list, config)
     }
 }
 
 impl PaintBoard {
+    /// 在后台启动绘制，返回可用于停止与等待的句柄
     pub fn start_daemon_arc(
         board: Arc<PaintBoard>,
         cookie_list: Arc<CookieList>,
         config: Arc<Config>,
+    ) -> DaemonHandle {
+        let shutdown = Arc::new(Shutdown::default());
+        let thread = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || {
+                PaintBoard::run_daemon(board, cookie_list, config, shutdown);
+            })
+        };
+        DaemonHandle::new(shutdown, thread)
+    }
+
+    /// 使用一个 Cookie 绘制 opt，失败时视情况重新加入队列
+    fn paint(
+        &self,
+        opt: &NodeOpt,
+        cookie_list: &CookieList,
+        config: &Config,
+        shutdown: &Shutdown,
+        rate_limiter: &TokenBucket,
     ) {
+        let cookie = match cookie_list.get_cookie(config, shutdown) {
+            Ok(Some(cookie)) => cookie,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("Failed to get cookie: {}", err);
+                self.forget(opt);
+                if config.cookie_reload_interval > 0 {
+                    // 等待重新读取 cookie_dir 时加入新的 Cookie
+                    shutdown.wait_timeout(std::time::Duration::from_secs(
+                        config.cookie_reload_interval,
+                    ));
+                } else {
+                    shutdown.trigger(DaemonExit::OutOfCookies);
+                }
+                return;
+            }
+        };
+        if !rate_limiter.acquire(shutdown) {
+            return;
+        }
+        // 等待令牌时可能已经过了很久，冷却从请求实际发出时开始计算
+        cookie_list.update(&cookie);
+
+        match opt.update(&cookie, config, &self.client) {
+            Ok(()) => {
+                log::warn!("Update success");
+                self.throughput.record_success();
+                cookie_list.record_success(&cookie);
+            }
+            Err(err) => {
+                log::warn!("Failed to paint ({}, {}): {}", opt.x, opt.y, err);
+                self.throughput.record_failure();
+                match err {
+                    ScriptError::PaintRejected(PaintError::OutOfBounds)
+                    | ScriptError::PaintRejected(PaintError::InvalidColor) => {
+                        // 重试也不会成功，放弃这个点
+                        self.targets.remove_target(opt.x, opt.y);
+                    }
+                    ScriptError::CookieOutdated => {
+                        log::warn!("Remove outdated cookie");
+                        cookie_list.remove_cookie(&cookie);
+                    }
+                    ScriptError::PaintRejected(PaintError::RateLimited)
+                    | ScriptError::PaintRejected(PaintError::ServerError(..)) => {
+                        // 推迟所有线程的下一次请求，服务器错误同样与 Cookie 无关
+                        rate_limiter
+                            .pause(std::time::Duration::from_secs(config.rate_limit.backoff));
+                    }
+                    // 网络错误与 Cookie 无关
+                    ScriptError::FailedProcessRequest(_) => {}
+                    _ => cookie_list.record_failure(&cookie, config),
+                }
+                self.forget(opt);
+            }
+        }
+        save_cookie_state(cookie_list, config);
+    }
+
+    fn run_daemon(
+        board: Arc<PaintBoard>,
+        cookie_list: Arc<CookieList>,
+        config: Arc<Config>,
+        shutdown: Arc<Shutdown>,
+    ) {
+        use std::sync::atomic::AtomicBool;
         use threadpool::ThreadPool;
         let pool = ThreadPool::new(config.thread_num);
+        // 在第一次获取到画板前，无法判断是否已经完成
+        let refreshed = Arc::new(AtomicBool::new(false));
+        board.load_cache(&config);
         {
             let board = board.clone();
             let config = config.clone();
+            let shutdown = shutdown.clone();
+            let refreshed = refreshed.clone();
             pool.execute(move || {
                 log::info!("Start auto refresh daemon");
-                loop {
-                    board.refresh_board(&config);
-                    std::thread::sleep(std::time::Duration::from_secs(120));
+                while !shutdown.is_triggered() {
+                    // just log and skip if the process failed to get board from remote server
+                    match board.refresh_board(&config) {
+                        Ok(()) => {
+                            refreshed.store(true, Ordering::SeqCst);
+                            board.save_cache(&config);
+                        }
+                        Err(err) => log::error!("Failed to refresh board: {}", err),
+                    }
+                    shutdown.wait_timeout(std::time::Duration::from_secs(120));
                 }
+                log::info!("Auto refresh daemon stopped");
             });
         }
         {
             let board = board.clone();
             let config = config.clone();
+            let shutdown = shutdown.clone();
             pool.execute(move || {
                 log::info!("Start websocket update daemon");
-                use tungstenite::{client, protocol::Message};
-                loop {
+                use tungstenite::{client, protocol::Message, stream::MaybeTlsStream};
+                while !shutdown.is_triggered() {
                     // TODO: What to do if init connect failed?
                     let mut client = match client::connect(&config.websocket_addr) {
                         Ok(data) => data.0,
                         Err(_) => break,
                     };
-                    if let Err(_) = client.write_message(Message::text(
-                        "{\"type\":\"join_channel\",\"channel\":\"paintboard\"}",
-                    )) {
+                    if client
+                        .write_message(Message::text(
+                            "{\"type\":\"join_channel\",\"channel\":\"paintboard\"}",
+                        ))
+                        .is_err()
+                    {
                         break;
                     };
+                    // 定期从阻塞读取中返回，以便检查停止信号
+                    let timeout = Some(std::time::Duration::from_secs(1));
+                    let timeout_set = match client.get_ref() {
+                        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(timeout),
+                        MaybeTlsStream::NativeTls(stream) => {
+                            stream.get_ref().set_read_timeout(timeout)
+                        }
+                        _ => Ok(()),
+                    };
+                    if let Err(err) = timeout_set {
+                        log::warn!("Failed to set websocket read timeout: {}", err);
+                    }
                     log::info!("Websocket conn est, wait for messages");
                     let mut first_req = false;
-                    loop {
+                    while !shutdown.is_triggered() {
                         let message = client.read_message();
+                        if let Err(tungstenite::Error::Io(err)) = &message {
+                            if err.kind() == std::io::ErrorKind::WouldBlock
+                                || err.kind() == std::io::ErrorKind::TimedOut
+                            {
+                                continue;
+                            }
+                        }
                         if !first_req {
                             first_req = true;
                             continue;
@@ -214,7 +610,11 @@ impl PaintBoard {
                             Ok(message) => {
                                 if let Message::Text(message) = message {
                                     if let Ok(update) = serde_json::from_str::<NodeOpt>(&message) {
-                                        board.set_color(update.x, update.y, Some(update.color));
+                                        if let Err(err) =
+                                            board.set_color(update.x, update.y, Some(update.color))
+                                        {
+                                            log::warn!("Ignored websocket update: {}", err);
+                                        }
                                     }
                                 }
                             }
@@ -224,43 +624,309 @@ impl PaintBoard {
                             }
                         }
                     }
+                    if shutdown.is_triggered() {
+                        client.close(None).ok();
+                        break;
+                    }
                     log::info!("Websocket error, try reconnect...");
                 }
+                log::info!("Websocket update daemon stopped");
             });
         }
-        let last_update_time = Arc::new(Mutex::new(std::time::Instant::now()));
-        loop {
+        if config.cookie_reload_interval > 0 {
             let cookie_list = cookie_list.clone();
-            let board = board.clone();
             let config = config.clone();
-            let last_update_time = last_update_time.clone();
-            while pool.max_count() <= pool.active_count() {
-                // TODO: Set with config
-                std::thread::sleep(std::time::Duration::from_millis(500));
-            }
+            let shutdown = shutdown.clone();
             pool.execute(move || {
-                use crate::ScriptError;
-                let opt = board.get_update();
-                let cookie = cookie_list.get_cookie(&config);
-                {
-                    let mut last_update_time = last_update_time.lock().unwrap();
-                    while std::time::Instant::now() - *last_update_time
-                        <= std::time::Duration::from_millis(500)
-                    {
-                        std::thread::sleep(std::time::Duration::from_millis(300));
+                log::info!("Start cookie reload daemon");
+                let interval = std::time::Duration::from_secs(config.cookie_reload_interval);
+                while !shutdown.wait_timeout(interval) {
+                    // 文件可能正在写入，读取失败时保留当前列表
+                    match init::get_cookie_from_dir(&config.cookie_dir) {
+                        Ok(loaded) => {
+                            let unreadable: Vec<_> = loaded
+                                .failed
+                                .into_iter()
+                                .map(|(path, err)| {
+                                    log::warn!("Failed to read cookie file {:?}: {}", path, err);
+                                    path
+                                })
+                                .collect();
+                            let (added, removed) =
+                                cookie_list.sync(loaded.cookies.into(), &unreadable);
+                            if added > 0 || removed > 0 {
+                                log::info!(
+                                    "Reloaded cookies: {} added, {} removed",
+                                    added,
+                                    removed
+                                );
+                            }
+                        }
+                        Err(err) => log::error!("Failed to reload cookies: {}", err),
                     }
-                    *last_update_time = std::time::Instant::now();
                 }
-
-                if let Err(err) = opt.update(&cookie, &config) {
-                    board.set_color(opt.x, opt.y, None);
-                    if let ScriptError::CookieOutdated = err {
-                        cookie_list.remove_cookie(&cookie);
+                log::info!("Cookie reload daemon stopped");
+            });
+        }
+        if config.node_reload_interval > 0 {
+            let board = board.clone();
+            let config = config.clone();
+            let shutdown = shutdown.clone();
+            pool.execute(move || {
+                log::info!("Start node reload daemon");
+                let interval = std::time::Duration::from_secs(config.node_reload_interval);
+                let modified = || {
+                    std::fs::metadata(&config.node_file)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                };
+                let mut last_modified = modified();
+                while !shutdown.wait_timeout(interval) {
+                    let cur_modified = modified();
+                    if cur_modified == last_modified {
+                        continue;
                     }
-                } else {
-                    log::warn!("Update success");
+                    // 文件可能正在写入，读取失败时保留当前目标，下次继续尝试
+                    match init::get_node(&config.node_file, &config.palette) {
+                        Ok(list) => {
+                            let len = list.len();
+                            match board.replace_targets(&config, list) {
+                                Ok(()) => log::info!("Reloaded {} targets from node file", len),
+                                Err(err) => log::error!("Ignored node file: {}", err),
+                            }
+                            last_modified = cur_modified;
+                        }
+                        Err(err) => log::error!("Failed to reload node file: {}", err),
+                    }
+                }
+                log::info!("Node reload daemon stopped");
+            });
+        }
+        let rate_limiter = Arc::new(TokenBucket::new(&config.rate_limit, Arc::new(SystemClock)));
+        let progress_interval = std::time::Duration::from_secs(config.progress_interval);
+        let mut last_progress = std::time::Instant::now();
+        while !shutdown.is_triggered() {
+            if config.progress_interval > 0 && last_progress.elapsed() >= progress_interval {
+                log::info!(
+                    "Progress: {}",
+                    board.stats(cookie_list.paint_rate(config.wait_time), &config)
+                );
+                last_progress = std::time::Instant::now();
+            }
+            if config.exit_on_complete && refreshed.load(Ordering::SeqCst) && board.is_complete() {
+                log::info!("All targets are painted");
+                shutdown.trigger(DaemonExit::Completed);
+                break;
+            }
+            if pool.max_count() <= pool.active_count() {
+                // TODO: Set with config
+                shutdown.wait_timeout(std::time::Duration::from_millis(500));
+                continue;
+            }
+            let cookie_list = cookie_list.clone();
+            let board = board.clone();
+            let config = config.clone();
+            let shutdown = shutdown.clone();
+            let rate_limiter = rate_limiter.clone();
+            pool.execute(move || {
+                let opt = match board.get_update(&shutdown) {
+                    Some(opt) => opt,
+                    None => return,
+                };
+                let paint = std::panic::AssertUnwindSafe(|| {
+                    board.paint(&opt, &cookie_list, &config, &shutdown, &rate_limiter)
+                });
+                if std::panic::catch_unwind(paint).is_err() {
+                    log::error!("Worker panicked while painting ({}, {})", opt.x, opt.y);
+                    board.forget(&opt);
                 }
             });
         }
+        log::info!("Waiting for workers to stop");
+        cookie_list.notify();
+        pool.join();
+        save_cookie_state(&cookie_list, &config);
+        if refreshed.load(Ordering::SeqCst) {
+            board.save_cache(&config);
+        }
+    }
+}
+
+/// 按配置保存 Cookie 状态，失败时只记录日志
+fn save_cookie_state(cookie_list: &CookieList, config: &Config) {
+    if let Some(path) = &config.cookie_state_file {
+        if let Err(err) = cookie_list.save_state(path) {
+            log::error!("Failed to save cookie state: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::{Cookie, RawCookie};
+
+    use std::time::Duration;
+
+    fn config() -> Arc<Config> {
+        Arc::new(Config {
+            wait_time: 0,
+            board_width: 4,
+            board_height: 4,
+            ..Config::default()
+        })
+    }
+
+    fn node(x: usize, y: usize, color: usize) -> NodeOpt {
+        NodeOpt { x, y, color }
+    }
+
+    #[test]
+    fn replace_targets() {
+        let config = config();
+        let board = PaintBoard::new(config.clone(), vec![node(0, 0, 2)].into()).unwrap();
+        board.set_color(0, 0, Some(2)).unwrap();
+        board.set_color(1, 1, Some(3)).unwrap();
+        assert!(board.is_complete());
+
+        board
+            .replace_targets(&config, vec![node(1, 1, 3), node(2, 2, 5)].into())
+            .unwrap();
+        assert_eq!(board.targets.color(0, 0), None);
+        assert!(!board.is_complete());
+        // (1, 1) 已经是目标颜色，只有 (2, 2) 需要绘制
+        let shutdown = Shutdown::default();
+        let opt = board.get_update(&shutdown).unwrap();
+        assert_eq!((opt.x, opt.y, opt.color), (2, 2, 5));
+        assert!(board.targets.queue_empty());
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let config = config();
+        assert!(matches!(
+            PaintBoard::new(config.clone(), vec![node(0, 0, 2), node(4, 0, 2)].into()),
+            Err(ScriptError::OutOfBounds(4, 0))
+        ));
+        let board = PaintBoard::new(config.clone(), vec![node(3, 3, 2)].into()).unwrap();
+        assert!(matches!(
+            board.set_color(0, 4, Some(2)),
+            Err(ScriptError::OutOfBounds(0, 4))
+        ));
+        assert_eq!(board.color.color(0, 4), None);
+
+        // 超出范围的目标不会替换原有目标
+        assert!(matches!(
+            board.replace_targets(&config, vec![node(1, 1, 3), node(1, 9, 3)].into()),
+            Err(ScriptError::OutOfBounds(1, 9))
+        ));
+        assert_eq!(board.targets.color(3, 3), Some(2));
+        assert_eq!(board.targets.color(1, 1), None);
+
+        assert!(matches!(
+            board.color.check_size(vec![4, 4, 5]),
+            Err(ScriptError::OutOfBounds(2, 4))
+        ));
+        assert!(matches!(
+            board.color.check_size(vec![4, 4, 4, 4, 1]),
+            Err(ScriptError::OutOfBounds(4, 0))
+        ));
+        assert!(board.color.check_size(vec![4, 2, 4, 0, 0]).is_ok());
+    }
+
+    #[test]
+    fn color_array() {
+        let array = ColorArray::new(config());
+        assert_eq!(array.color(3, 3), Some(1));
+        array.set_color(1, 2, None).unwrap();
+        assert_eq!(array.color(1, 2), None);
+        assert!(matches!(
+            array.set_color(0, 0, Some(255)),
+            Err(ScriptError::UnknownColor(_))
+        ));
+
+        array
+            .replace_all(&[vec![5usize, 6], vec![], vec![7, 8, 9, 10]])
+            .unwrap();
+        assert_eq!(array.color(0, 1), Some(6));
+        // 没有新颜色的点保持不变
+        assert_eq!(array.color(0, 2), Some(1));
+        assert_eq!(array.color(1, 2), None);
+        assert_eq!(array.to_vec()[2], vec![Some(7), Some(8), Some(9), Some(10)]);
+
+        // 有一个点不合法时不写入任何点
+        assert!(array.replace_all(&[vec![Some(2), Some(300)]]).is_err());
+        assert!(matches!(
+            array.replace_all(&[vec![2usize; 5]]),
+            Err(ScriptError::OutOfBounds(0, 4))
+        ));
+        assert_eq!(array.color(0, 0), Some(5));
+    }
+
+    /// 使用模拟服务器的 4x4 画板
+    fn simulated(
+        status: Option<u16>,
+        list: VecDeque<NodeOpt>,
+    ) -> (crate::simulator::SimulatorHandle, Arc<Config>, PaintBoard) {
+        let simulator = crate::simulator::Simulator::new(4, 4, 32, Duration::ZERO)
+            .start("127.0.0.1:0", "127.0.0.1:0")
+            .unwrap();
+        simulator.simulator().set_outage(status);
+        let config = Arc::new(Config {
+            board_addr: simulator.board_addr.clone(),
+            websocket_addr: simulator.websocket_addr.clone(),
+            wait_time: 0,
+            board_width: 4,
+            board_height: 4,
+            ..Config::default()
+        });
+        let board = PaintBoard::new(config.clone(), list).unwrap();
+        (simulator, config, board)
+    }
+
+    #[test]
+    fn invalid_board() {
+        assert!(matches!(
+            parse_board("01\n0!"),
+            Err(ScriptError::InvalidBoardData(1, 1))
+        ));
+        let (_simulator, config, board) = simulated(Some(503), VecDeque::new());
+        assert!(matches!(
+            board.refresh_board(&config),
+            Err(ScriptError::FailedProcessRequest(_))
+        ));
+        // 获取失败时保留原有的画板
+        assert_eq!(board.color.color(0, 0), Some(1));
+    }
+
+    #[test]
+    fn requeue_failed_paint() {
+        let (_simulator, config, board) = simulated(Some(503), vec![node(1, 2, 5)].into());
+        let shutdown = Shutdown::default();
+        let cookie_list =
+            CookieList::new(vec![Cookie::new(RawCookie::new("a".to_string()))].into());
+        let rate_limiter = TokenBucket::new(&config.rate_limit, Arc::new(SystemClock));
+
+        let opt = board.get_update(&shutdown).unwrap();
+        assert!(board.targets.queue_empty());
+        board.paint(&opt, &cookie_list, &config, &shutdown, &rate_limiter);
+        assert_eq!(board.color.color(1, 2), None);
+        let opt = board.get_update(&shutdown).unwrap();
+        assert_eq!((opt.x, opt.y, opt.color), (1, 2, 5));
+    }
+
+    #[test]
+    fn server_error() {
+        let (_simulator, config, board) = simulated(Some(503), vec![node(1, 2, 5)].into());
+        let shutdown = Shutdown::default();
+        let cookie_list =
+            CookieList::new(vec![Cookie::new(RawCookie::new("a".to_string()))].into());
+        let rate_limiter = TokenBucket::new(&config.rate_limit, Arc::new(SystemClock));
+
+        let opt = board.get_update(&shutdown).unwrap();
+        board.paint(&opt, &cookie_list, &config, &shutdown, &rate_limiter);
+        // 服务器故障不计入 Cookie 的失败次数，并推迟所有请求
+        assert_eq!(cookie_list.stats()[0].state.failures, 0);
+        assert!(matches!(rate_limiter.try_acquire(), Err(wait) if wait > Duration::from_secs(1)));
     }
 }
@@ -0,0 +1,395 @@
+use crate::ScriptError;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// 绘板以 32 进制传输颜色，因此最多支持 32 种颜色
+pub const MAX_COLORS: usize = 32;
+
+/// 洛谷冬日绘板的默认调色板，下标即为颜色编号
+const LUOGU_COLORS: [[u8; 3]; 32] = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [170, 170, 170],
+    [85, 85, 85],
+    [254, 211, 199],
+    [255, 196, 206],
+    [250, 172, 142],
+    [255, 139, 131],
+    [244, 67, 54],
+    [233, 30, 99],
+    [226, 102, 158],
+    [156, 39, 176],
+    [103, 58, 183],
+    [63, 81, 181],
+    [0, 70, 112],
+    [5, 113, 151],
+    [33, 150, 243],
+    [0, 188, 212],
+    [59, 229, 219],
+    [151, 253, 220],
+    [22, 115, 0],
+    [55, 169, 60],
+    [137, 230, 66],
+    [215, 255, 7],
+    [255, 246, 209],
+    [248, 203, 140],
+    [255, 235, 59],
+    [255, 193, 7],
+    [255, 152, 0],
+    [255, 87, 34],
+    [184, 63, 39],
+    [121, 85, 72],
+];
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Empty,
+    TooManyColors(usize),
+    DuplicateName(String),
+    InvalidName(String),
+}
+
+impl std::fmt::Display for PaletteError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PaletteError::Empty => write!(formatter, "Palette is empty!"),
+            PaletteError::TooManyColors(len) => write!(
+                formatter,
+                "Palette has {} colors, at most {} are supported!",
+                len, MAX_COLORS
+            ),
+            PaletteError::DuplicateName(name) => {
+                write!(formatter, "Duplicate color name in palette: {}", name)
+            }
+            PaletteError::InvalidName(name) => {
+                write!(formatter, "Invalid color name in palette: {}", name)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// 配置文件中的单个颜色
+pub struct PaletteColor {
+    #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+    pub rgb: [u8; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+/// 配置文件中的 `[palette]`
+struct RawPalette {
+    colors: Vec<PaletteColor>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "RawPalette", into = "RawPalette")]
+/// 调色板
+/// 颜色编号 -> RGB
+pub struct Palette {
+    colors: Vec<PaletteColor>,
+    lab: Vec<[f64; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new(LUOGU_COLORS.to_vec())
+    }
+}
+
+impl From<RawPalette> for Palette {
+    fn from(raw_palette: RawPalette) -> Palette {
+        Palette::with_colors(raw_palette.colors)
+    }
+}
+
+impl From<Palette> for RawPalette {
+    fn from(palette: Palette) -> RawPalette {
+        RawPalette {
+            colors: palette.colors,
+        }
+    }
+}
+
+/// 解析 `#rrggbb` 格式的颜色
+pub fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn serialize_hex<S: Serializer>(rgb: &[u8; 3], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]))
+}
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 3], D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    parse_hex(&hex)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid color {}, expected #rrggbb", hex)))
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Palette {
+        Palette::with_colors(
+            colors
+                .into_iter()
+                .map(|rgb| PaletteColor { rgb, name: None })
+                .collect(),
+        )
+    }
+    pub fn with_colors(colors: Vec<PaletteColor>) -> Palette {
+        let lab = colors.iter().map(|color| rgb_to_lab(color.rgb)).collect();
+        Palette { colors, lab }
+    }
+
+    /// 检查调色板能否用于绘板
+    pub fn check(&self) -> Result<(), ScriptError> {
+        if self.colors.is_empty() {
+            return Err(ScriptError::InvalidPalette(PaletteError::Empty));
+        }
+        if self.colors.len() > MAX_COLORS {
+            return Err(ScriptError::InvalidPalette(PaletteError::TooManyColors(
+                self.colors.len(),
+            )));
+        }
+        let mut names = std::collections::HashSet::new();
+        for name in self.colors.iter().filter_map(|color| color.name.as_ref()) {
+            // 名称不能与颜色编号或十六进制颜色混淆
+            if name.is_empty() || name.starts_with('#') || name.parse::<usize>().is_ok() {
+                return Err(ScriptError::InvalidPalette(PaletteError::InvalidName(
+                    name.clone(),
+                )));
+            }
+            if !names.insert(name) {
+                return Err(ScriptError::InvalidPalette(PaletteError::DuplicateName(
+                    name.clone(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+    pub fn contains(&self, color: usize) -> bool {
+        color < self.colors.len()
+    }
+    pub fn rgb(&self, color: usize) -> Option<[u8; 3]> {
+        self.colors.get(color).map(|color| color.rgb)
+    }
+    pub fn name(&self, color: usize) -> Option<&str> {
+        self.colors.get(color)?.name.as_deref()
+    }
+
+    /// 通过颜色名称或 `#rrggbb` 查找颜色编号
+    pub fn find(&self, color: &str) -> Option<usize> {
+        match parse_hex(color) {
+            Some(rgb) => self.colors.iter().position(|cur| cur.rgb == rgb),
+            None => self
+                .colors
+                .iter()
+                .position(|cur| cur.name.as_deref() == Some(color)),
+        }
+    }
+
+    /// 找到 RGB 距离最近的颜色编号
+    pub fn nearest(&self, rgb: [u8; 3]) -> usize {
+        let distance = |color: &PaletteColor| -> u32 {
+            color
+                .rgb
+                .iter()
+                .zip(rgb.iter())
+                .map(|(a, b)| (i32::from(*a) - i32::from(*b)).pow(2) as u32)
+                .sum()
+        };
+        self.colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, color)| distance(color))
+            .map(|(index, _)| index)
+            .unwrap_or(0)
+    }
+
+    /// 在 CIELAB 空间中按 CIEDE2000 色差找到最接近的颜色编号
+    pub fn nearest_lab(&self, rgb: [u8; 3]) -> usize {
+        let lab = rgb_to_lab(rgb);
+        self.lab
+            .iter()
+            .map(|color| ciede2000(&lab, color))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(index, _)| index)
+            .unwrap_or(0)
+    }
+}
+
+/// sRGB -> CIELAB (D65)
+pub fn rgb_to_lab(rgb: [u8; 3]) -> [f64; 3] {
+    let linear = |c: u8| -> f64 {
+        let c = f64::from(c) / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(rgb[0]), linear(rgb[1]), linear(rgb[2]));
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.119192 * g + 0.9503041 * b) / 1.08883;
+
+    let f = |t: f64| -> f64 {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIEDE2000 色差
+pub fn ciede2000(lab1: &[f64; 3], lab2: &[f64; 3]) -> f64 {
+    use std::f64::consts::PI;
+
+    let [l1, a1, b1] = *lab1;
+    let [l2, a2, b2] = *lab2;
+
+    let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + 25f64.powi(7))).sqrt());
+    let a1 = (1.0 + g) * a1;
+    let a2 = (1.0 + g) * a2;
+    let c1 = (a1 * a1 + b1 * b1).sqrt();
+    let c2 = (a2 * a2 + b2 * b2).sqrt();
+    let hue = |b: f64, a: f64| -> f64 {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            let h = b.atan2(a);
+            if h < 0.0 {
+                h + 2.0 * PI
+            } else {
+                h
+            }
+        }
+    };
+    let h1 = hue(b1, a1);
+    let h2 = hue(b2, a2);
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= PI {
+        h2 - h1
+    } else if h2 - h1 > PI {
+        h2 - h1 - 2.0 * PI
+    } else {
+        h2 - h1 + 2.0 * PI
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= PI {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 2.0 * PI {
+        (h1 + h2 + 2.0 * PI) / 2.0
+    } else {
+        (h1 + h2 - 2.0 * PI) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - PI / 6.0).cos()
+        + 0.24 * (2.0 * h_bar).cos()
+        + 0.32 * (3.0 * h_bar + PI / 30.0).cos()
+        - 0.20 * (4.0 * h_bar - 63.0 * PI / 180.0).cos();
+    let delta_theta = PI / 6.0 * (-((h_bar.to_degrees() - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_bar.powi(7) / (c_bar.powi(7) + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).sin() * r_c;
+
+    ((delta_l / s_l).powi(2)
+        + (delta_c / s_c).powi(2)
+        + (delta_h / s_h).powi(2)
+        + r_t * (delta_c / s_c) * (delta_h / s_h))
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ciede2000_reference_pairs() {
+        // Sharma, Wu & Dalal 给出的测试数据
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([50.0, 2.5, 0.0], [50.0, 3.2592, 0.335], 1.0),
+        ];
+        for (lab1, lab2, expected) in pairs.iter() {
+            assert!((ciede2000(lab1, lab2) - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn nearest_lab_picks_palette_color() {
+        let palette = Palette::default();
+        assert_eq!(palette.nearest_lab([0, 0, 0]), 0);
+        assert_eq!(palette.nearest_lab([255, 255, 255]), 1);
+        assert_eq!(palette.nearest_lab([245, 65, 55]), 8);
+    }
+
+    #[test]
+    fn parse_palette_section() {
+        let palette: Palette = toml::from_str(
+            r##"
+            colors = [
+                { rgb = "#000000", name = "black" },
+                { rgb = "#FFFFFF" },
+            ]
+            "##,
+        )
+        .unwrap();
+
+        assert!(palette.check().is_ok());
+        assert_eq!(palette.len(), 2);
+        assert_eq!(palette.rgb(1), Some([255, 255, 255]));
+        assert_eq!(palette.find("black"), Some(0));
+        assert_eq!(palette.find("#ffffff"), Some(1));
+        assert_eq!(palette.find("#123456"), None);
+        assert_eq!(palette.find("white"), None);
+    }
+
+    #[test]
+    fn check_duplicate_name() {
+        let palette = Palette::with_colors(vec![
+            PaletteColor {
+                rgb: [0, 0, 0],
+                name: Some("black".to_string()),
+            },
+            PaletteColor {
+                rgb: [1, 1, 1],
+                name: Some("black".to_string()),
+            },
+        ]);
+
+        assert!(matches!(
+            palette.check(),
+            Err(ScriptError::InvalidPalette(PaletteError::DuplicateName(_)))
+        ));
+    }
+}
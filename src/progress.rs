@@ -0,0 +1,204 @@
+use crate::ratelimit::{Clock, SystemClock};
+use crate::Config;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 统计绘制速度的时间窗口
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// 最近一段时间内成功与失败的绘制
+pub struct Throughput {
+    successes: Mutex<VecDeque<Instant>>,
+    failures: Mutex<VecDeque<Instant>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Throughput {
+    fn default() -> Throughput {
+        Throughput::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl Throughput {
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Throughput {
+        Throughput {
+            successes: Mutex::new(VecDeque::new()),
+            failures: Mutex::new(VecDeque::new()),
+            clock,
+        }
+    }
+
+    pub fn record_success(&self) {
+        self.record(&self.successes);
+    }
+    pub fn record_failure(&self) {
+        self.record(&self.failures);
+    }
+    fn record(&self, events: &Mutex<VecDeque<Instant>>) {
+        let now = self.clock.now();
+        let mut events = events.lock().unwrap();
+        events.push_back(now);
+        prune(&mut events, now);
+    }
+
+    /// 最近一分钟内成功的绘制次数
+    pub fn paints_per_minute(&self) -> usize {
+        self.count(&self.successes)
+    }
+    /// 最近一分钟内失败的绘制次数
+    pub fn failures_per_minute(&self) -> usize {
+        self.count(&self.failures)
+    }
+    fn count(&self, events: &Mutex<VecDeque<Instant>>) -> usize {
+        let mut events = events.lock().unwrap();
+        prune(&mut events, self.clock.now());
+        events.len()
+    }
+}
+
+fn prune(events: &mut VecDeque<Instant>, now: Instant) {
+    while let Some(first) = events.front() {
+        if now.saturating_duration_since(*first) < WINDOW {
+            break;
+        }
+        events.pop_front();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 绘制进度
+pub struct ProgressStats {
+    /// 目标点总数，不包括已放弃的点
+    pub total: usize,
+    /// 与目标一致的点数
+    pub correct: usize,
+    /// 与目标不一致或颜色未知的点数
+    pub wrong: usize,
+    pub paints_per_minute: usize,
+    pub failures_per_minute: usize,
+    /// 按未被暂停的 Cookie 各自的冷却时间与全局限速估计的剩余时间，无法估计时为 None
+    pub eta: Option<Duration>,
+}
+
+impl ProgressStats {
+    /// cookie_rate 为可用的 Cookie 每秒最多能绘制的次数，见 `CookieList::paint_rate`
+    pub fn new(
+        correct: usize,
+        wrong: usize,
+        throughput: &Throughput,
+        cookie_rate: f64,
+        config: &Config,
+    ) -> ProgressStats {
+        ProgressStats {
+            total: correct + wrong,
+            correct,
+            wrong,
+            paints_per_minute: throughput.paints_per_minute(),
+            failures_per_minute: throughput.failures_per_minute(),
+            eta: estimate(wrong, cookie_rate, config),
+        }
+    }
+
+    /// 完成的百分比
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            100.0
+        } else {
+            self.correct as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+/// 每秒最多能绘制的点数受 Cookie 冷却与全局限速中较小者限制
+fn estimate(wrong: usize, cookie_rate: f64, config: &Config) -> Option<Duration> {
+    if wrong == 0 {
+        return Some(Duration::ZERO);
+    }
+    if cookie_rate <= 0.0 {
+        return None;
+    }
+    let mut rate = cookie_rate;
+    if config.rate_limit.requests_per_second > 0.0 {
+        rate = rate.min(config.rate_limit.requests_per_second);
+    }
+    if rate.is_infinite() {
+        return Some(Duration::ZERO);
+    }
+    Some(Duration::from_secs_f64(wrong as f64 / rate))
+}
+
+impl std::fmt::Display for ProgressStats {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "{}/{} correct ({:.1}%), {} wrong, {} paints/min, {} failures/min, ETA ",
+            self.correct,
+            self.total,
+            self.percent(),
+            self.wrong,
+            self.paints_per_minute,
+            self.failures_per_minute
+        )?;
+        match self.eta {
+            Some(eta) => {
+                let secs = eta.as_secs();
+                write!(
+                    formatter,
+                    "{}:{:02}:{:02}",
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60
+                )
+            }
+            None => write!(formatter, "unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ratelimit::MockClock;
+
+    #[test]
+    fn throughput_window() {
+        let clock = Arc::new(MockClock::default());
+        let throughput = Throughput::with_clock(clock.clone());
+        throughput.record_success();
+        clock.advance(Duration::from_secs(30));
+        throughput.record_success();
+        throughput.record_failure();
+        assert_eq!(throughput.paints_per_minute(), 2);
+        assert_eq!(throughput.failures_per_minute(), 1);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(throughput.paints_per_minute(), 1);
+    }
+
+    #[test]
+    fn progress_eta() {
+        let throughput = Throughput::default();
+        let mut config = Config::default();
+        // 3 个 Cookie 每 30 秒各绘制一次，比全局限速慢
+        let stats = ProgressStats::new(70, 30, &throughput, 0.1, &config);
+        assert_eq!(stats.percent(), 70.0);
+        assert_eq!(stats.eta, Some(Duration::from_secs(300)));
+        assert_eq!(
+            stats.to_string(),
+            "70/100 correct (70.0%), 30 wrong, 0 paints/min, 0 failures/min, ETA 0:05:00"
+        );
+
+        // Cookie 足够多时受全局限速限制
+        config.rate_limit.requests_per_second = 0.5;
+        let stats = ProgressStats::new(70, 30, &throughput, 10.0, &config);
+        assert_eq!(stats.eta, Some(Duration::from_secs(60)));
+
+        assert_eq!(
+            ProgressStats::new(70, 30, &throughput, 0.0, &config).eta,
+            None
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use super::daemon::Shutdown;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// 时间来源，测试时可以替换为 MockClock
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// 系统时钟
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 只有手动推进才会变化的时钟
+pub struct MockClock {
+    now: Mutex<Instant>,
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+/// 配置文件中的 `[rate_limit]`
+pub struct RateLimitConfig {
+    /// 所有 Cookie 合计每秒最多发出的绘制请求数，0 表示不限制
+    pub requests_per_second: f64,
+    /// 允许连续发出的请求数
+    pub burst: u32,
+    /// 被服务器限流或服务器出错后暂停所有请求的时间（秒）
+    pub backoff: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_second: 2.0,
+            burst: 1,
+            backoff: 10,
+        }
+    }
+}
+
+struct BucketState {
+    tokens: f64,
+    last: Instant,
+    paused_until: Option<Instant>,
+}
+
+/// 令牌桶
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    clock: Arc<dyn Clock>,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(config: &RateLimitConfig, clock: Arc<dyn Clock>) -> TokenBucket {
+        let burst = f64::from(config.burst.max(1));
+        TokenBucket {
+            rate: config.requests_per_second,
+            burst,
+            state: Mutex::new(BucketState {
+                tokens: burst,
+                last: clock.now(),
+                paused_until: None,
+            }),
+            clock,
+        }
+    }
+
+    /// 尝试取出一个令牌，失败时返回距离下一个令牌可用的时间
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        if self.rate <= 0.0 {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        if let Some(paused_until) = state.paused_until {
+            if paused_until > now {
+                return Err(paused_until - now);
+            }
+            state.paused_until = None;
+        }
+        let elapsed = now.saturating_duration_since(state.last).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.burst);
+        state.last = now;
+        // 避免浮点误差导致等待后仍差极小的一部分
+        if state.tokens >= 1.0 - 1e-9 {
+            state.tokens = (state.tokens - 1.0).max(0.0);
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - state.tokens) / self.rate))
+        }
+    }
+
+    /// 阻塞直到取得令牌，收到停止信号时返回 false
+    pub fn acquire(&self, shutdown: &Shutdown) -> bool {
+        loop {
+            match self.try_acquire() {
+                Ok(()) => return true,
+                Err(wait) => {
+                    if shutdown.wait_timeout(wait) {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 在 duration 内拒绝所有请求
+    pub fn pause(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let paused_until = self.clock.now() + duration;
+        state.paused_until = Some(paused_until);
+        // 暂停结束后从零开始积累令牌
+        state.tokens = 0.0;
+        state.last = paused_until;
+    }
+}
+
+/// 上次使用时间为 last 的 Cookie 还需要冷却多久
+pub fn cooldown_remaining(last: Instant, now: Instant, cooldown: Duration) -> Option<Duration> {
+    let ready = last + cooldown;
+    if ready > now {
+        Some(ready - now)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(requests_per_second: f64, burst: u32) -> (TokenBucket, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::default());
+        let config = RateLimitConfig {
+            requests_per_second,
+            burst,
+            backoff: 10,
+        };
+        (TokenBucket::new(&config, clock.clone()), clock)
+    }
+
+    #[test]
+    fn bucket_refills_at_rate() {
+        let (bucket, clock) = bucket(2.0, 1);
+        assert!(bucket.try_acquire().is_ok());
+        assert_eq!(bucket.try_acquire(), Err(Duration::from_millis(500)));
+
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(bucket.try_acquire(), Err(Duration::from_millis(300)));
+
+        clock.advance(Duration::from_millis(300));
+        assert!(bucket.try_acquire().is_ok());
+    }
+
+    #[test]
+    fn bucket_allows_burst() {
+        let (bucket, clock) = bucket(1.0, 3);
+        for _ in 0..3 {
+            assert!(bucket.try_acquire().is_ok());
+        }
+        assert!(bucket.try_acquire().is_err());
+
+        // 长时间空闲也不会超过 burst
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..3 {
+            assert!(bucket.try_acquire().is_ok());
+        }
+        assert!(bucket.try_acquire().is_err());
+    }
+
+    #[test]
+    fn bucket_pause() {
+        let (bucket, clock) = bucket(10.0, 5);
+        bucket.pause(Duration::from_secs(10));
+        assert_eq!(bucket.try_acquire(), Err(Duration::from_secs(10)));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(bucket.try_acquire(), Err(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn unlimited_bucket() {
+        let (bucket, _) = bucket(0.0, 1);
+        for _ in 0..100 {
+            assert!(bucket.try_acquire().is_ok());
+        }
+    }
+
+    #[test]
+    fn cooldown() {
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(30);
+        assert_eq!(
+            cooldown_remaining(now, now + Duration::from_secs(10), cooldown),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            cooldown_remaining(now, now + Duration::from_secs(30), cooldown),
+            None
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use crate::paintboard::{ColorArray, TargetList};
+use crate::palette::Palette;
+use crate::ScriptError;
+
+use std::path::Path;
+
+use image::{ImageFormat, Rgba, RgbaImage};
+
+const TRANSPARENT: Rgba<u8> = Rgba([0, 0, 0, 0]);
+/// 差异图中与目标一致的点
+const CORRECT: Rgba<u8> = Rgba([0x4c, 0xaf, 0x50, 0xff]);
+/// 差异图中与目标不一致或颜色未知的点
+const WRONG: Rgba<u8> = Rgba([0xf4, 0x43, 0x36, 0xff]);
+
+fn palette_color(palette: &Palette, color: Option<usize>) -> Option<[u8; 3]> {
+    color.and_then(|color| palette.rgb(color))
+}
+
+/// 将第 x 行第 y 个为 (x, y) 颜色的数组绘制为图片，未知颜色为透明
+pub fn render_colors(colors: &[Vec<Option<usize>>], palette: &Palette) -> RgbaImage {
+    let height = colors.first().map_or(0, Vec::len);
+    RgbaImage::from_fn(
+        colors.len() as u32,
+        height as u32,
+        |x, y| match palette_color(palette, colors[x as usize][y as usize]) {
+            Some([r, g, b]) => Rgba([r, g, b, 0xff]),
+            None => TRANSPARENT,
+        },
+    )
+}
+
+/// 绘制画板
+pub fn render_board(array: &ColorArray, palette: &Palette) -> RgbaImage {
+    render_colors(&array.to_vec(), palette)
+}
+
+/// 绘制所有目标点，其余位置为透明
+pub fn render_targets(targets: &TargetList, palette: &Palette) -> RgbaImage {
+    render_colors(&targets.to_vec(), palette)
+}
+
+/// 绘制差异图：与目标一致的点为绿色，不一致的点为红色，
+/// 不是目标的点为变暗的画板颜色
+pub fn render_diff(
+    board: &[Vec<Option<usize>>],
+    targets: &[Vec<Option<usize>>],
+    palette: &Palette,
+) -> RgbaImage {
+    let height = board.first().map_or(0, Vec::len);
+    RgbaImage::from_fn(board.len() as u32, height as u32, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let current = board[x][y];
+        match targets
+            .get(x)
+            .and_then(|line| line.get(y))
+            .copied()
+            .flatten()
+        {
+            Some(target) if current == Some(target) => CORRECT,
+            Some(_) => WRONG,
+            None => match palette_color(palette, current) {
+                Some([r, g, b]) => Rgba([r / 2, g / 2, b / 2, 0xff]),
+                None => Rgba([0, 0, 0, 0xff]),
+            },
+        }
+    })
+}
+
+/// 保存为 PNG 文件
+pub fn save_png<T>(image: &RgbaImage, path: &T) -> Result<(), ScriptError>
+where
+    T: AsRef<Path>,
+{
+    image.save_with_format(path.as_ref(), ImageFormat::Png)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let palette = Palette::new(vec![[0, 0, 0], [200, 100, 50]]);
+        let board = vec![vec![Some(0), Some(1)], vec![None, Some(1)]];
+        let targets = vec![vec![Some(0), None], vec![Some(1), Some(0)]];
+
+        let image = render_colors(&board, &palette);
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(*image.get_pixel(0, 1), Rgba([200, 100, 50, 0xff]));
+        assert_eq!(*image.get_pixel(1, 0), TRANSPARENT);
+
+        let diff = render_diff(&board, &targets, &palette);
+        assert_eq!(*diff.get_pixel(0, 0), CORRECT);
+        assert_eq!(*diff.get_pixel(0, 1), Rgba([100, 50, 25, 0xff]));
+        assert_eq!(*diff.get_pixel(1, 0), WRONG);
+        assert_eq!(*diff.get_pixel(1, 1), WRONG);
+    }
+}
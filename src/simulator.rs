@@ -0,0 +1,273 @@
+use crate::{to_32, ScriptError};
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 本地绘板模拟服务器
+/// 提供与洛谷绘板相同的 `GET /board`、`POST /paint?token=` 以及 WebSocket 推送接口，
+/// 用于在没有网络的情况下测试整个绘制流程
+pub struct Simulator {
+    width: usize,
+    height: usize,
+    colors: usize,
+    wait_time: Duration,
+    board: Mutex<Vec<Vec<usize>>>,
+    tokens: Option<HashSet<String>>,
+    last_paint: Mutex<HashMap<String, Instant>>,
+    subscribers: Mutex<Vec<mpsc::Sender<String>>>,
+    /// 模拟故障时所有 HTTP 请求返回的状态码
+    outage: Mutex<Option<u16>>,
+}
+
+/// 正在运行的模拟服务器
+pub struct SimulatorHandle {
+    pub board_addr: String,
+    pub websocket_addr: String,
+    simulator: Arc<Simulator>,
+}
+
+impl SimulatorHandle {
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+}
+
+impl Simulator {
+    pub fn new(width: usize, height: usize, colors: usize, wait_time: Duration) -> Simulator {
+        Simulator {
+            width,
+            height,
+            colors,
+            wait_time,
+            board: Mutex::new(vec![vec![1; height]; width]),
+            tokens: None,
+            last_paint: Mutex::new(HashMap::new()),
+            subscribers: Mutex::new(Vec::new()),
+            outage: Mutex::new(None),
+        }
+    }
+
+    /// 只接受给定的 token，默认接受任意 token
+    pub fn with_tokens(mut self, tokens: HashSet<String>) -> Simulator {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// 模拟服务器故障，之后所有 HTTP 请求都返回 status，None 表示恢复正常
+    pub fn set_outage(&self, status: Option<u16>) {
+        *self.outage.lock().unwrap() = status;
+    }
+
+    pub fn color(&self, x: usize, y: usize) -> Option<usize> {
+        self.board.lock().unwrap().get(x)?.get(y).copied()
+    }
+
+    /// 在后台启动 HTTP 与 WebSocket 服务，地址中的端口为 0 时自动分配
+    pub fn start<A, B>(
+        self,
+        http_addr: A,
+        websocket_addr: B,
+    ) -> Result<SimulatorHandle, ScriptError>
+    where
+        A: std::net::ToSocketAddrs,
+        B: std::net::ToSocketAddrs,
+    {
+        let http = TcpListener::bind(http_addr)?;
+        let websocket = TcpListener::bind(websocket_addr)?;
+        let handle = SimulatorHandle {
+            board_addr: format!("http://{}", http.local_addr()?),
+            websocket_addr: format!("ws://{}", websocket.local_addr()?),
+            simulator: Arc::new(self),
+        };
+        {
+            let simulator = handle.simulator.clone();
+            std::thread::spawn(move || {
+                for stream in http.incoming().flatten() {
+                    let simulator = simulator.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = simulator.serve_http(stream) {
+                            log::debug!("Simulator failed to serve http: {}", err);
+                        }
+                    });
+                }
+            });
+        }
+        {
+            let simulator = handle.simulator.clone();
+            std::thread::spawn(move || {
+                for stream in websocket.incoming().flatten() {
+                    let simulator = simulator.clone();
+                    std::thread::spawn(move || simulator.serve_websocket(stream));
+                }
+            });
+        }
+        Ok(handle)
+    }
+
+    fn board_text(&self) -> String {
+        let board = self.board.lock().unwrap();
+        let mut text = String::with_capacity(self.width * (self.height + 1));
+        for line in board.iter() {
+            text.extend(line.iter().map(|color| to_32(*color)));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// 返回 Luogu 格式的状态
+    fn paint(&self, token: Option<&str>, form: &HashMap<String, String>) -> (u32, &'static str) {
+        let token = match token {
+            Some(token) if !token.is_empty() => token,
+            _ => return (401, "没有登录"),
+        };
+        if let Some(tokens) = &self.tokens {
+            if !tokens.contains(token) {
+                return (403, "Token 无效");
+            }
+        }
+        let field = |name: &str| form.get(name).and_then(|value| value.parse::<usize>().ok());
+        let (x, y, color) = match (field("x"), field("y"), field("color")) {
+            (Some(x), Some(y), Some(color)) => (x, y, color),
+            _ => return (400, "参数不合法"),
+        };
+        if x >= self.width || y >= self.height {
+            return (400, "坐标超出范围");
+        }
+        if color >= self.colors {
+            return (400, "颜色不合法");
+        }
+        {
+            let mut last_paint = self.last_paint.lock().unwrap();
+            let now = Instant::now();
+            if let Some(last) = last_paint.get(token) {
+                if now - *last < self.wait_time {
+                    return (500, "冷却时间未到");
+                }
+            }
+            last_paint.insert(token.to_string(), now);
+        }
+        self.board.lock().unwrap()[x][y] = color;
+        let message = format!(
+            "{{\"type\":\"paintboard_update\",\"x\":{},\"y\":{},\"color\":{}}}",
+            x, y, color
+        );
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(message.clone()).is_ok());
+        (200, "")
+    }
+
+    fn serve_http(&self, stream: TcpStream) -> Result<(), ScriptError> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("").to_string();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (target.as_str(), ""),
+        };
+        let query: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        let form: HashMap<String, String> =
+            url::form_urlencoded::parse(&body).into_owned().collect();
+
+        let outage = *self.outage.lock().unwrap();
+        let (code, body) = if let Some(status) = outage {
+            (status, "Service Unavailable".to_string())
+        } else if method == "GET" && path.ends_with("/board") {
+            (200, self.board_text())
+        } else if method == "POST" && path.ends_with("/paint") {
+            match self.paint(query.get("token").map(String::as_str), &form) {
+                (200, _) => (200, "[]".to_string()),
+                (status, data) => (
+                    200,
+                    format!("{{\"status\":{},\"data\":\"{}\"}}", status, data),
+                ),
+            }
+        } else {
+            (404, "Not Found".to_string())
+        };
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            code,
+            match code {
+                200 => "OK",
+                404 => "Not Found",
+                _ => "Error",
+            },
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn serve_websocket(&self, stream: TcpStream) {
+        use tungstenite::Message;
+
+        let mut websocket = match tungstenite::accept(stream) {
+            Ok(websocket) => websocket,
+            Err(err) => {
+                log::debug!("Simulator websocket handshake failed: {}", err);
+                return;
+            }
+        };
+        // 等待 join_channel 后才开始推送
+        loop {
+            match websocket.read_message() {
+                Ok(Message::Text(message)) if message.contains("join_channel") => break,
+                Ok(_) => continue,
+                Err(_) => return,
+            }
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        if websocket
+            .write_message(Message::text("{\"type\":\"result\",\"id\":0}"))
+            .is_err()
+        {
+            return;
+        }
+        for message in receiver {
+            if websocket.write_message(Message::text(message)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// 解析 `host:port`，用于从配置中的 URL 得到监听地址
+pub fn listen_addr(url: &str) -> Result<SocketAddr, ScriptError> {
+    let url = url::Url::parse(url)?;
+    let addrs = url.socket_addrs(|| None)?;
+    addrs
+        .into_iter()
+        .next()
+        .ok_or(ScriptError::FailedParseUrl(url::ParseError::EmptyHost))
+}
@@ -0,0 +1,188 @@
+use crate::paintboard::ColorArray;
+use crate::palette::Palette;
+use crate::{write_atomic, ScriptError};
+
+use std::path::Path;
+
+/// 快照文件开头的标识
+const MAGIC: &[u8; 4] = b"LPBS";
+const VERSION: u8 = 1;
+/// 未知颜色在快照中的值
+const UNKNOWN: u8 = u8::MAX;
+
+/// 画板快照
+///
+/// 文件格式（整数均为小端序）：
+/// - 4 字节标识 `LPBS` 与 1 字节版本号
+/// - 宽度与高度，各 4 字节
+/// - 调色板颜色数 1 字节，之后每种颜色 3 字节 RGB
+/// - 每个点 1 字节颜色编号，按 x 再按 y 的顺序排列，`0xff` 表示未知
+pub struct Snapshot {
+    pub width: usize,
+    pub height: usize,
+    pub palette: Palette,
+    /// 第 x 行第 y 个为 (x, y) 的颜色
+    pub colors: Vec<Vec<Option<usize>>>,
+}
+
+impl Snapshot {
+    pub fn from_array(array: &ColorArray, palette: &Palette) -> Snapshot {
+        Snapshot {
+            width: array.width(),
+            height: array.height(),
+            palette: palette.clone(),
+            colors: array.to_vec(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(14 + self.palette.len() * 3 + self.width * self.height);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.width as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.height as u32).to_le_bytes());
+        bytes.push(self.palette.len() as u8);
+        for color in 0..self.palette.len() {
+            bytes.extend_from_slice(&self.palette.rgb(color).unwrap_or_default());
+        }
+        for line in &self.colors {
+            bytes.extend(
+                line.iter()
+                    .map(|color| color.map_or(UNKNOWN, |color| color as u8)),
+            );
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Snapshot, ScriptError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(ScriptError::InvalidSnapshot("not a board snapshot"));
+        }
+        if reader.take(1)?[0] != VERSION {
+            return Err(ScriptError::InvalidSnapshot("unsupported version"));
+        }
+        let width = reader.u32()? as usize;
+        let height = reader.u32()? as usize;
+        let palette_len = reader.take(1)?[0] as usize;
+        let palette = Palette::new(
+            reader
+                .take(palette_len * 3)?
+                .chunks(3)
+                .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                .collect(),
+        );
+        let pixels = reader.take(width * height)?;
+        if !reader.bytes.is_empty() {
+            return Err(ScriptError::InvalidSnapshot("trailing data"));
+        }
+        let colors = pixels
+            .chunks(height.max(1))
+            .take(width)
+            .map(|line| {
+                line.iter()
+                    .map(|&color| match color {
+                        UNKNOWN => Ok(None),
+                        color if (color as usize) < palette_len => Ok(Some(color as usize)),
+                        _ => Err(ScriptError::InvalidSnapshot("color out of palette")),
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Snapshot {
+            width,
+            height,
+            palette,
+            colors,
+        })
+    }
+
+    pub fn save<T>(&self, path: &T) -> Result<(), ScriptError>
+    where
+        T: AsRef<Path>,
+    {
+        write_atomic(path, self.encode())
+    }
+
+    pub fn load<T>(path: &T) -> Result<Snapshot, ScriptError>
+    where
+        T: AsRef<Path>,
+    {
+        Snapshot::decode(&std::fs::read(path.as_ref())?)
+    }
+
+    /// 快照的尺寸与调色板是否与 palette 和给定的尺寸一致
+    pub fn matches(&self, width: usize, height: usize, palette: &Palette) -> bool {
+        self.width == width
+            && self.height == height
+            && self.palette.len() == palette.len()
+            && (0..palette.len()).all(|color| self.palette.rgb(color) == palette.rgb(color))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ScriptError> {
+        if self.bytes.len() < len {
+            return Err(ScriptError::InvalidSnapshot("unexpected end of file"));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+    fn u32(&mut self) -> Result<u32, ScriptError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            width: 3,
+            height: 2,
+            palette: Palette::new(vec![[0, 0, 0], [255, 255, 255], [255, 0, 0]]),
+            colors: vec![
+                vec![Some(0), Some(1)],
+                vec![None, Some(2)],
+                vec![Some(2), None],
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = snapshot().encode();
+        assert_eq!(bytes.len(), 4 + 1 + 8 + 1 + 9 + 6);
+        let decoded = Snapshot::decode(&bytes).unwrap();
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert_eq!(decoded.colors, snapshot().colors);
+        assert!(decoded.matches(3, 2, &snapshot().palette));
+        assert!(!decoded.matches(3, 2, &Palette::default()));
+    }
+
+    #[test]
+    fn invalid_snapshot() {
+        let bytes = snapshot().encode();
+        assert!(matches!(
+            Snapshot::decode(&bytes[..bytes.len() - 1]),
+            Err(ScriptError::InvalidSnapshot(_))
+        ));
+        assert!(matches!(
+            Snapshot::decode(b"PNG\0"),
+            Err(ScriptError::InvalidSnapshot(_))
+        ));
+        let mut bytes = bytes;
+        *bytes.last_mut().unwrap() = 3;
+        assert!(matches!(
+            Snapshot::decode(&bytes),
+            Err(ScriptError::InvalidSnapshot(_))
+        ));
+    }
+}
@@ -1,44 +1,165 @@
-use std::{process, sync::Arc, thread};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
 use draw_script::{
-    cookie::CookieList,
+    client,
+    cookie::{self, Cookie, CookieList},
+    daemon::DaemonExit,
+    health::{self, CookieHealth},
     init,
-    paintboard::{ColorArray, PaintBoard, TargetList},
+    node::NodeOpt,
+    paintboard::PaintBoard,
+    simulator::{Simulator, SimulatorHandle},
+    snapshot::Snapshot,
     Config,
 };
 
+/// 在临时目录下准备 Cookies 与绘图数据
+fn prepare_dir(name: &str, cookies: &[&str], nodes: &[[usize; 3]]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("draw-script-{}-{}", name, std::process::id()));
+    let cookie_dir = dir.join("cookies");
+    std::fs::create_dir_all(&cookie_dir).unwrap();
+    for (i, cookie) in cookies.iter().enumerate() {
+        std::fs::write(
+            cookie_dir.join(format!("{}.json", i)),
+            format!("{{\"cookie\": \"{}\"}}", cookie),
+        )
+        .unwrap();
+    }
+    std::fs::write(dir.join("node.json"), serde_json::to_string(nodes).unwrap()).unwrap();
+    dir
+}
+
+fn test_config(dir: &Path, simulator: &SimulatorHandle) -> Config {
+    Config {
+        board_addr: simulator.board_addr.clone(),
+        websocket_addr: simulator.websocket_addr.clone(),
+        cookie_dir: dir.join("cookies").to_string_lossy().into_owned(),
+        node_file: dir.join("node.json").to_string_lossy().into_owned(),
+        wait_time: 1,
+        thread_num: 5,
+        board_width: 40,
+        board_height: 30,
+        exit_on_complete: true,
+        cookie_reload_interval: 1,
+        node_reload_interval: 0,
+        cookie_state_file: Some(dir.join("cookie_state.json").to_string_lossy().into_owned()),
+        board_cache: Some(dir.join("board.bin").to_string_lossy().into_owned()),
+        progress_interval: 1,
+        ..Config::default()
+    }
+}
+
 #[test]
 fn test() {
     pretty_env_logger::init();
-    let config = Arc::new(
-        Config::new("config.toml".to_string()).unwrap_or_else(|err| {
-            panic!("Error parsing the config file: {}", err);
-        }),
+    let nodes: Vec<[usize; 3]> = (0..3)
+        .flat_map(|x| (0..3).map(move |y| [5 + x, 7 + y, (x * 3 + y) % 4 + 2]))
+        .collect();
+    let dir = prepare_dir(
+        "test",
+        &[
+            "_uid=0;__client_id=test",
+            "_uid=1;__client_id=test",
+            "_uid=2;__client_id=test",
+        ],
+        &nodes,
     );
+    let simulator = Simulator::new(40, 30, 32, Duration::from_secs(1))
+        .start("127.0.0.1:0", "127.0.0.1:0")
+        .unwrap_or_else(|err| {
+            panic!("Error starting the simulator: {}", err);
+        });
+    let config = Arc::new(test_config(&dir, &simulator));
+    config.validate().unwrap();
     let cookie_list = CookieList::new(
-        init::get_cookie_from_dir(&config.cookie_dir).unwrap_or_else(|err| {
-            panic!("Error getting cookies: {}", err);
-        }),
+        init::get_cookie_from_dir(&config.cookie_dir)
+            .unwrap_or_else(|err| {
+                panic!("Error getting cookies: {}", err);
+            })
+            .cookies,
     );
-    let paint_board = PaintBoard {
-        color: ColorArray::new(Arc::clone(&config)),
-        targets: TargetList::new(
-            Arc::clone(&config),
-            init::get_node(&config.node_file).unwrap_or_else(|err| {
-                panic!("Error getting nodes: {}", err);
-            }),
-        ),
-    };
-    let paint_board = Arc::new(paint_board);
-    let monitor = Arc::clone(&paint_board);
-    thread::spawn(move || {
-        PaintBoard::start_daemon_arc(paint_board, Arc::from(cookie_list), Arc::clone(&config))
+    let targets: VecDeque<NodeOpt> = init::get_node(&config.node_file, &config.palette)
+        .unwrap_or_else(|err| {
+            panic!("Error getting nodes: {}", err);
+        });
+    let paint_board = PaintBoard::new(Arc::clone(&config), targets).unwrap_or_else(|err| {
+        panic!("Error creating the paint board: {}", err);
     });
+    let handle = paint_board.start_daemon(Arc::from(cookie_list), Arc::clone(&config));
 
-    loop {
-        if monitor.targets.queue_empty() {
-            eprintln!("Test complete!");
-            process::exit(0);
-        }
+    // 超时后停止，此时结束原因为 Stopped
+    let shutdown = handle.shutdown();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_secs(60));
+        shutdown.trigger(DaemonExit::Stopped);
+    });
+    assert_eq!(handle.wait(), DaemonExit::Completed);
+    for node in nodes.iter() {
+        assert_eq!(simulator.simulator().color(node[0], node[1]), Some(node[2]));
+    }
+    let state = cookie::load_state(config.cookie_state_file.as_ref().unwrap()).unwrap();
+    let successes: u64 = state.values().map(|state| state.successes).sum();
+    assert!(successes >= nodes.len() as u64);
+    let cache = Snapshot::load(config.board_cache.as_ref().unwrap()).unwrap();
+    assert_eq!((cache.width, cache.height), (40, 30));
+    for node in nodes.iter() {
+        assert_eq!(cache.colors[node[0]][node[1]], Some(node[2]));
     }
+    eprintln!("Test complete!");
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn check_cookies() {
+    let valid = "_uid=1;__client_id=valid";
+    let expired = "_uid=2;__client_id=expired";
+    let malformed = "not a cookie";
+    let dir = prepare_dir("check-cookies", &[valid, expired, malformed], &[]);
+    let simulator = Simulator::new(40, 30, 32, Duration::from_secs(1))
+        .with_tokens(std::iter::once(valid.to_string()).collect())
+        .start("127.0.0.1:0", "127.0.0.1:0")
+        .unwrap_or_else(|err| {
+            panic!("Error starting the simulator: {}", err);
+        });
+    let config = test_config(&dir, &simulator);
+    let cookies: Vec<Cookie> = init::get_cookie_from_dir(&config.cookie_dir)
+        .unwrap()
+        .cookies
+        .into_iter()
+        .collect();
+    let client = client::build_client(&config).unwrap();
+
+    let reports = health::check_cookies(&cookies, &config, &client).unwrap();
+    let report = |cookie: &str| {
+        reports
+            .iter()
+            .find(|report| report.cookie == cookie)
+            .unwrap()
+    };
+    assert!(matches!(report(valid).health, CookieHealth::Valid));
+    assert!(matches!(report(expired).health, CookieHealth::Expired));
+    assert!(matches!(
+        report(malformed).health,
+        CookieHealth::Malformed(_)
+    ));
+    // 只有发出了检测请求的 Cookie 需要从检测时开始冷却
+    assert!(report(valid).probed_at.is_some());
+    assert!(report(malformed).probed_at.is_none());
+
+    let quarantine_dir = dir.join("quarantine");
+    assert_eq!(health::quarantine(&reports, &quarantine_dir).unwrap(), 2);
+    assert_eq!(
+        init::get_cookie_from_dir(&config.cookie_dir)
+            .unwrap()
+            .cookies
+            .len(),
+        1
+    );
+    std::fs::remove_dir_all(dir).ok();
 }